@@ -0,0 +1,120 @@
+pub mod layers;
+pub mod lint;
+use std::fs;
+use serde::{Deserialize, Serialize};
+use crate::logging::LogConfig;
+use crate::service::{api::ApiConfig, auth::AuthConfig, filter::FilterConfig, history::HistoryConfig,
+                     metrics::MetricsConfig, queue::QueueConfig, rules::{Action, Rule}};
+
+// Settings read from the optional TOML file passed to `install --config`.
+// Every section is optional, so an empty file behaves like no file at all.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub service: ServiceConfig,
+    pub filter: FilterConfig,
+    pub auth: Option<AuthConfig>,
+    pub rules: Vec<Rule>,
+    pub api: Option<ApiConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub history: HistoryConfig,
+    pub queue: QueueConfig,
+    pub log: LogConfig,
+}
+
+// The `[service]` config section: overrides for the settings given at install time,
+// so they can be changed with a reload instead of a reinstall.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub program: Option<String>,
+    pub run_path: Option<String>,
+    pub host_ip: Option<String>,
+    // Record what would be started instead of starting it, like `run --dry-run`.
+    pub dry_run: bool,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("Failed to parse config file {}: {}", path, e))?;
+        config.validate().map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(api) = &self.api {
+            api.validate()?;
+        }
+        for rule in &self.rules {
+            for running in rule.running.iter().chain(rule.fallback.as_ref().and_then(|fallback| fallback.running.as_ref())) {
+                running.validate().map_err(|e| format!("rule {}: running: {}", rule.name, e))?;
+            }
+            for limits in rule.limits.iter().chain(rule.fallback.as_ref().and_then(|fallback| fallback.limits.as_ref())) {
+                limits.validate().map_err(|e| format!("rule {}: limits: {}", rule.name, e))?;
+            }
+            if let Some(supervise) = &rule.supervise {
+                supervise.validate().map_err(|e| format!("rule {}: supervise: {}", rule.name, e))?;
+            }
+            if let Some(ready) = &rule.ready {
+                ready.validate().map_err(|e| format!("rule {}: ready: {}", rule.name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    // The program to start when no rule overrides it.
+    pub fn default_action(&self, installed: &Action) -> Action {
+        Action {
+            program: self.service.program.clone().unwrap_or_else(|| installed.program.clone()),
+            run_path: self.service.run_path.clone().unwrap_or_else(|| installed.run_path.clone()),
+            running: None,
+            user: None,
+            limits: None,
+        }
+    }
+
+    // The prefix selecting the capture interface.
+    pub fn host_ip(&self, installed: &str) -> String {
+        self.service.host_ip.clone().unwrap_or_else(|| installed.to_string())
+    }
+
+    // The configured rules, or the implicit default rule if there are none.
+    pub fn effective_rules(&self) -> Vec<Rule> {
+        if self.rules.is_empty() {
+            vec![Rule::default_rule()]
+        } else {
+            self.rules.clone()
+        }
+    }
+
+    // The config as the service applies it, for `config show`: implicit rules and
+    // paths filled in, secrets masked.
+    pub fn effective(&self, installed: &Action, installed_host_ip: &str) -> Config {
+        let default_action = &self.default_action(installed);
+        let mut config = self.clone();
+        config.service = ServiceConfig {
+            program: Some(default_action.program.clone()),
+            run_path: Some(default_action.run_path.clone()),
+            host_ip: Some(self.host_ip(installed_host_ip)),
+            dry_run: self.service.dry_run,
+        };
+        config.rules = self.effective_rules().into_iter()
+            .map(|rule| {
+                let action = rule.action(default_action);
+                Rule { program: Some(action.program), run_path: Some(action.run_path), ..rule }
+            })
+            .collect();
+        config.history.path = Some(self.history.path());
+        config.log.dir = Some(self.log.dir());
+        if let Some(auth) = config.auth.as_mut() {
+            auth.key = String::from("<redacted>");
+        }
+        if let Some(token) = config.api.as_mut().and_then(|api| api.token.as_mut()) {
+            *token = String::from("<redacted>");
+        }
+        config
+    }
+}
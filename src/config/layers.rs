@@ -0,0 +1,242 @@
+use std::{env, fmt, fs, io, path::PathBuf};
+use clap::ArgMatches;
+use serde::Deserialize;
+use super::ServiceConfig;
+
+// Where a setting got its value. Later variants take precedence over earlier ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    SystemFile(PathBuf),
+    UserFile(PathBuf),
+    Environment(&'static str),
+    Flag(&'static str),
+    // The launch arguments of the installed service.
+    Installed,
+    // The `[service]` section of a config file.
+    ConfigFile(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::SystemFile(path) => write!(f, "system file {}", path.display()),
+            Source::UserFile(path) => write!(f, "user file {}", path.display()),
+            Source::Environment(var) => write!(f, "environment variable {}", var),
+            Source::Flag(flag) => write!(f, "flag --{}", flag),
+            Source::Installed => write!(f, "installed service"),
+            Source::ConfigFile(path) => write!(f, "[service] in {}", path),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layered<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Layered<T> {
+    fn new(value: T) -> Layered<T> {
+        Layered { value, source: Source::Default }
+    }
+
+    fn set(&mut self, value: T, source: Source) {
+        self.value = value;
+        self.source = source;
+    }
+}
+
+// The settings commands fall back to when neither the installed service nor a flag gives one,
+// resolved from built-in defaults < system file < user file < WOLSTART_* variables < flags.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub program: Layered<String>,
+    pub run_path: Layered<String>,
+    pub host_ip: Layered<String>,
+    pub log_level: Layered<String>,
+    pub send_address: Layered<String>,
+    pub send_port: Layered<u16>,
+    // Layers that could not be read; their values are skipped.
+    pub warnings: Vec<String>,
+}
+
+// A system or user settings file, with the same keys as `config show --sources` prints.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SettingsFile {
+    program: Option<String>,
+    run_path: Option<String>,
+    host_ip: Option<String>,
+    log_level: Option<String>,
+    send_address: Option<String>,
+    send_port: Option<u16>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        let (program, run_path) = if cfg!(windows) {
+            ("kodi.exe", "C:\\Program Files\\Kodi\\kodi.exe")
+        } else {
+            ("kodi", "/usr/bin/kodi")
+        };
+        Settings {
+            program: Layered::new(program.to_string()),
+            run_path: Layered::new(run_path.to_string()),
+            host_ip: Layered::new(String::from("192.168.1.132")),
+            log_level: Layered::new(String::from("warn")),
+            send_address: Layered::new(String::from("255.255.255.255")),
+            send_port: Layered::new(9),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    // `matches` are the parsed command line; flags of the chosen subcommand form the top layer.
+    pub fn load(matches: &ArgMatches) -> Settings {
+        let mut settings = Settings::default();
+        settings.apply_file(system_file(), Source::SystemFile);
+        if let Some(path) = user_file() {
+            settings.apply_file(path, Source::UserFile);
+        }
+        settings.apply_environment();
+        if let Some((_, sub_matches)) = matches.subcommand() {
+            settings.apply_flags(sub_matches);
+        }
+        settings
+    }
+
+    fn apply_file(&mut self, path: PathBuf, source: fn(PathBuf) -> Source) {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                self.warnings.push(format!("Ignoring settings file {}: {}", path.display(), e));
+                return;
+            }
+        };
+        let file: SettingsFile = match toml::from_str(&content) {
+            Ok(file) => file,
+            Err(e) => {
+                self.warnings.push(format!("Ignoring settings file {}: {}", path.display(), e));
+                return;
+            }
+        };
+        let fields = [
+            (&mut self.program, file.program),
+            (&mut self.run_path, file.run_path),
+            (&mut self.host_ip, file.host_ip),
+            (&mut self.log_level, file.log_level),
+            (&mut self.send_address, file.send_address),
+        ];
+        for (setting, value) in fields {
+            if let Some(value) = value {
+                setting.set(value, source(path.clone()));
+            }
+        }
+        if let Some(port) = file.send_port {
+            self.send_port.set(port, source(path));
+        }
+    }
+
+    fn apply_environment(&mut self) {
+        let fields = [
+            (&mut self.program, "WOLSTART_PROGRAM"),
+            (&mut self.run_path, "WOLSTART_RUN_PATH"),
+            (&mut self.host_ip, "WOLSTART_HOST_IP"),
+            (&mut self.log_level, "WOLSTART_LOG_LEVEL"),
+            (&mut self.send_address, "WOLSTART_SEND_ADDRESS"),
+        ];
+        for (setting, var) in fields {
+            match env::var(var) {
+                Ok(value) => setting.set(value, Source::Environment(var)),
+                Err(env::VarError::NotPresent) => {},
+                Err(e) => self.warnings.push(format!("Ignoring {}: {}", var, e)),
+            }
+        }
+        if let Ok(port) = env::var("WOLSTART_SEND_PORT") {
+            match port.parse() {
+                Ok(port) => self.send_port.set(port, Source::Environment("WOLSTART_SEND_PORT")),
+                Err(e) => self.warnings.push(format!("Ignoring WOLSTART_SEND_PORT {}: {}", port, e)),
+            }
+        }
+    }
+
+    // Subcommands only define some of these flags; the others are simply absent.
+    fn apply_flags(&mut self, matches: &ArgMatches) {
+        let fields = [
+            (&mut self.program, "program"),
+            (&mut self.run_path, "run-path"),
+            (&mut self.host_ip, "host-ip"),
+            (&mut self.log_level, "log-level"),
+            (&mut self.send_address, "address"),
+        ];
+        for (setting, flag) in fields {
+            if let Ok(Some(value)) = matches.try_get_one::<String>(flag) {
+                setting.set(value.clone(), Source::Flag(flag));
+            }
+        }
+        if let Ok(Some(port)) = matches.try_get_one::<u16>("port") {
+            self.send_port.set(*port, Source::Flag("port"));
+        }
+    }
+
+    // `arguments` are the installed service's launch arguments: program, run path, host ip, log level.
+    pub fn apply_installed(&mut self, arguments: &[String]) {
+        let fields = [&mut self.program, &mut self.run_path, &mut self.host_ip, &mut self.log_level];
+        for (setting, value) in fields.into_iter().zip(arguments) {
+            setting.set(value.clone(), Source::Installed);
+        }
+    }
+
+    pub fn apply_service_section(&mut self, service: &ServiceConfig, path: &str) {
+        let fields = [
+            (&mut self.program, &service.program),
+            (&mut self.run_path, &service.run_path),
+            (&mut self.host_ip, &service.host_ip),
+        ];
+        for (setting, value) in fields {
+            if let Some(value) = value {
+                setting.set(value.clone(), Source::ConfigFile(path.to_string()));
+            }
+        }
+    }
+
+    // Each setting as a TOML line, commented with where its value came from.
+    pub fn describe(&self) -> Vec<String> {
+        let quote = |value: &str| toml::Value::from(value).to_string();
+        let lines = [
+            ("program", quote(&self.program.value), &self.program.source),
+            ("run_path", quote(&self.run_path.value), &self.run_path.source),
+            ("host_ip", quote(&self.host_ip.value), &self.host_ip.source),
+            ("log_level", quote(&self.log_level.value), &self.log_level.source),
+            ("send_address", quote(&self.send_address.value), &self.send_address.source),
+            ("send_port", self.send_port.value.to_string(), &self.send_port.source),
+        ];
+        lines.iter()
+            .map(|(key, value, source)| format!("{:<12} = {:<40} # {}", key, value, source))
+            .collect()
+    }
+}
+
+pub fn system_file() -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into()))
+            .join("WolStart")
+            .join("wolstart.toml")
+    } else {
+        PathBuf::from("/etc/wolstart/wolstart.toml")
+    }
+}
+
+pub fn user_file() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    dir.map(|dir| dir.join(if cfg!(windows) { "WolStart" } else { "wolstart" }).join("wolstart.toml"))
+}
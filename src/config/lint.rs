@@ -0,0 +1,184 @@
+use std::{fmt, fs, path::Path};
+use pnet::datalink;
+use crate::service::{self, rules::Action};
+use super::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// A problem found by `config validate`, with where in the config it was found.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+// Checks a parsed config against this machine: interfaces, program paths and rule order.
+// `host_ip` and `default_action` are what the service is (or would be) installed with.
+pub fn lint(config: &Config, host_ip: &str, default_action: &Action) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |severity, location: String, message: String| {
+        diagnostics.push(Diagnostic { severity, location, message });
+    };
+
+    // Whether the installed unit lets the service apply the rules' limits and users.
+    #[cfg(unix)]
+    let sandbox = crate::systemd::installed_sandbox().ok().flatten();
+
+    let interfaces = datalink::interfaces();
+    if !interfaces.iter().any(|interface| service::matches_host_ip(interface, host_ip)) {
+        report(Severity::Error, String::from("host ip"), format!("no interface has an IP address starting with {}", host_ip));
+    }
+    for (list, rules) in [("filter.allow", &config.filter.allow), ("filter.deny", &config.filter.deny)] {
+        for (index, rule) in rules.iter().enumerate() {
+            if let Some(selector) = &rule.interface {
+                let found = interfaces.iter().any(|interface| selector.eq_ignore_ascii_case(&interface.name)
+                    || selector.eq_ignore_ascii_case(&interface.description));
+                if !found {
+                    report(Severity::Warning, format!("{} #{}", list, index + 1),
+                           format!("no interface is named or described {}", selector));
+                }
+            }
+        }
+    }
+
+    if let Some(auth) = &config.auth {
+        if auth.key.is_empty() {
+            report(Severity::Error, String::from("auth.key"), String::from("the key is empty"));
+        } else if auth.key.len() < 16 {
+            report(Severity::Warning, String::from("auth.key"), format!("a {} byte key is easy to guess", auth.key.len()));
+        }
+    }
+
+    for (index, rule) in config.rules.iter().enumerate() {
+        let location = format!("rule #{} ({})", index + 1, rule.name);
+        if rule.name.is_empty() {
+            report(Severity::Warning, location.clone(), String::from("has no name, so it cannot be triggered through the API"));
+        } else if let Some(first) = config.rules[..index].iter().position(|other| other.name == rule.name) {
+            report(Severity::Error, location.clone(), format!("has the same name as rule #{}", first + 1));
+        }
+        // The first rule whose target matches decides, whatever its schedule says.
+        let shadowing = config.rules[..index].iter()
+            .position(|other| other.target_mac.is_none() || other.target_mac == rule.target_mac);
+        if let Some(first) = shadowing {
+            let target = match config.rules[first].target_mac {
+                Some(mac) => format!("target {}", mac),
+                None => String::from("every target"),
+            };
+            report(Severity::Error, location.clone(),
+                   format!("is unreachable, rule #{} ({}) matches {} first", first + 1, config.rules[first].name, target));
+        }
+        let action = rule.action(default_action);
+        if let Some(problem) = executable_problem(&action.run_path) {
+            report(Severity::Error, location.clone(), problem);
+        }
+        if let Some(problem) = action.user.as_deref().and_then(user_problem) {
+            report(Severity::Error, location.clone(), problem);
+        }
+        #[cfg(unix)]
+        if let Some(sandbox) = &sandbox {
+            let fallback = rule.fallback_action().map(|fallback| (format!("{} fallback", location), fallback));
+            for (location, action) in std::iter::once((location.clone(), action.clone())).chain(fallback) {
+                let missing = sandbox.missing(&action);
+                if !missing.is_empty() {
+                    report(Severity::Warning, location,
+                           format!("the installed unit {}, reinstall the service to start the program as configured", missing.join(" and ")));
+                }
+            }
+        }
+        if cfg!(windows) && (rule.limits.is_some() || rule.fallback.as_ref().is_some_and(|fallback| fallback.limits.is_some())) {
+            report(Severity::Warning, location.clone(), String::from("limits are only applied on Linux"));
+        }
+        if let Some(fallback) = &rule.fallback {
+            if let Some(problem) = executable_problem(&fallback.run_path) {
+                report(Severity::Error, format!("{} fallback", location), problem);
+            }
+            if let Some(problem) = fallback.user.as_deref().and_then(user_problem) {
+                report(Severity::Error, format!("{} fallback", location), problem);
+            }
+        }
+    }
+    if config.rules.is_empty() {
+        if let Some(problem) = executable_problem(&default_action.run_path) {
+            report(Severity::Error, String::from("run path"), problem);
+        }
+    }
+    diagnostics
+}
+
+fn executable_problem(run_path: &str) -> Option<String> {
+    // Linux starts a bare program name from PATH, like a shell does.
+    #[cfg(unix)]
+    if !run_path.contains('/') {
+        let found = std::env::var_os("PATH")
+            .is_some_and(|path| std::env::split_paths(&path).any(|dir| file_problem(&dir.join(run_path)).is_none()));
+        return (!found).then(|| format!("{} is not an executable on PATH", run_path));
+    }
+    file_problem(Path::new(run_path))
+}
+
+fn file_problem(run_path: &Path) -> Option<String> {
+    let metadata = match fs::metadata(run_path) {
+        Ok(metadata) => metadata,
+        Err(e) => return Some(format!("{} cannot be found: {}", run_path.display(), e)),
+    };
+    if !metadata.is_file() {
+        return Some(format!("{} is not a file", run_path.display()));
+    }
+    #[cfg(windows)]
+    {
+        let executable = run_path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| ["exe", "com", "bat", "cmd"].iter().any(|known| extension.eq_ignore_ascii_case(known)));
+        if !executable {
+            return Some(format!("{} is not an executable", run_path.display()));
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Some(format!("{} is not executable", run_path.display()));
+        }
+    }
+    None
+}
+
+#[cfg(unix)]
+fn user_problem(user: &str) -> Option<String> {
+    service::session::Account::lookup(user).is_none().then(|| format!("user {} does not exist", user))
+}
+
+// Windows accounts are only matched against the users logged on when a program starts.
+#[cfg(windows)]
+fn user_problem(_user: &str) -> Option<String> {
+    None
+}
+
+// Windows starts programs by their full path only.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_bare_names_on_path() {
+        assert_eq!(executable_problem("sh"), None);
+        assert_eq!(executable_problem("/bin/sh"), None);
+        assert_eq!(executable_problem("wolstart-no-such-program"), Some(String::from("wolstart-no-such-program is not an executable on PATH")));
+        assert!(executable_problem("./sh").is_some_and(|problem| problem.contains("cannot be found")));
+    }
+}
@@ -0,0 +1,371 @@
+use std::{env, fmt, fs::{self, File, OpenOptions}, io::{self, Write}, path::PathBuf, str::FromStr, sync::Mutex,
+          time::{Duration, SystemTime}};
+use chrono::{Local, SecondsFormat};
+use log::{kv::{self, VisitSource}, warn, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use simplelog::{ColorChoice, CombinedLogger, Config, SharedLogger, TermLogger, TerminalMode, WriteLogger};
+
+#[cfg(unix)]
+mod journald;
+#[cfg(unix)]
+mod syslog;
+
+const LOG_FILE_NAME: &str = "wol_service";
+
+// The `[log]` config section.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // Where records at the service's log level go besides the terminal.
+    pub sink: LogSink,
+    // Directory for wol_service.log and its rotated copies; see `default_dir`.
+    pub dir: Option<PathBuf>,
+    pub terminal_level: String,
+    pub terminal_format: LogFormat,
+    pub file_format: LogFormat,
+    // Rotate once the file grows past this many megabytes; 0 disables.
+    pub max_size_mb: u64,
+    // Rotate once the file is older than this many days; 0 disables.
+    pub max_age_days: u64,
+    // How many rotated files are kept next to the active one.
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            sink: LogSink::File,
+            dir: None,
+            terminal_level: String::from("warn"),
+            terminal_format: LogFormat::Text,
+            file_format: LogFormat::Text,
+            max_size_mb: 10,
+            max_age_days: 7,
+            max_files: 5,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn dir(&self) -> PathBuf {
+        self.dir.clone().unwrap_or_else(default_dir)
+    }
+}
+
+// How a sink writes records: simplelog's plain lines, or one JSON object per line for log shippers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    // The rotating wol_service.log in `dir`.
+    File,
+    // The systemd journal, with wake event details as WOL_* fields.
+    Journald,
+    // The local syslog socket, in RFC 5424 format.
+    Syslog,
+}
+
+impl fmt::Display for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogSink::File => write!(f, "file"),
+            LogSink::Journald => write!(f, "journald"),
+            LogSink::Syslog => write!(f, "syslog"),
+        }
+    }
+}
+
+// A service runs from System32, so never default to the working directory.
+pub fn default_dir() -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into()))
+            .join("WolStart")
+            .join("logs")
+    } else {
+        PathBuf::from("/var/log/wolstart")
+    }
+}
+
+pub fn parse_level(level: &str) -> LevelFilter {
+    match level {
+        "off" => LevelFilter::Off,
+        "trace" => LevelFilter::Trace,
+        "debug" => LevelFilter::Debug,
+        "warn"=> LevelFilter::Warn,
+        "error" => LevelFilter::Error,
+        _ => LevelFilter::Info,
+    }
+}
+
+// Logs to the terminal and, if it can be opened, to the configured sink.
+pub fn init(config: &LogConfig, terminal_level: LevelFilter, file_level: LevelFilter) {
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![match config.terminal_format {
+        LogFormat::Text => TermLogger::new(terminal_level, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
+        // Keep stdout clean for the output of subcommands like `history --json`.
+        LogFormat::Json => JsonLogger::new(terminal_level, io::stderr()),
+    }];
+    let sink_error = match open_sink(config, file_level) {
+        Ok(logger) => {
+            loggers.push(logger);
+            None
+        },
+        Err(e) => Some(e),
+    };
+    CombinedLogger::init(loggers).unwrap();
+    if let Some(e) = sink_error {
+        warn!("Logging to the terminal only, {}", e);
+    }
+}
+
+fn open_sink(config: &LogConfig, level: LevelFilter) -> Result<Box<dyn SharedLogger>, String> {
+    match config.sink {
+        LogSink::File => {
+            let file = RotatingFile::open(config)
+                .map_err(|e| format!("cannot open a log file in {}: {}", config.dir().display(), e))?;
+            Ok(match config.file_format {
+                LogFormat::Text => WriteLogger::new(level, Config::default(), file),
+                LogFormat::Json => JsonLogger::new(level, file),
+            })
+        },
+        #[cfg(unix)]
+        LogSink::Journald => journald::JournaldLogger::connect(level)
+            .map(|logger| logger as Box<dyn SharedLogger>)
+            .map_err(|e| format!("cannot connect to the systemd journal: {}", e)),
+        #[cfg(unix)]
+        LogSink::Syslog => syslog::SyslogLogger::connect(level)
+            .map(|logger| logger as Box<dyn SharedLogger>)
+            .map_err(|e| format!("cannot connect to syslog: {}", e)),
+        #[cfg(not(unix))]
+        LogSink::Journald | LogSink::Syslog => Err(format!("the {} log sink is only available on Linux", config.sink)),
+    }
+}
+
+// The record's key-values, e.g. from `info!(target_mac:% = mac, rule = name; "...")`.
+fn fields(record: &Record<'_>) -> Map<String, Value> {
+    let mut fields = Map::new();
+    let _ = record.key_values().visit(&mut Fields(&mut fields));
+    fields
+}
+
+// A field value as plain text, without the quotes JSON puts around strings.
+#[cfg(unix)]
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+// The syslog(3) severity of a level, which is also the journal's PRIORITY as used by journalctl -p.
+#[cfg(unix)]
+fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+// Writes each record as a JSON object with timestamp, level, module, message and the
+// record's key-values.
+pub struct JsonLogger {
+    level: LevelFilter,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    pub fn new<W: Write + Send + 'static>(level: LevelFilter, output: W) -> Box<JsonLogger> {
+        Box::new(JsonLogger { level, output: Mutex::new(Box::new(output)) })
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut object = Map::new();
+        object.insert(String::from("timestamp"), Local::now().to_rfc3339_opts(SecondsFormat::Millis, false).into());
+        object.insert(String::from("level"), record.level().as_str().into());
+        object.insert(String::from("module"), record.module_path().unwrap_or(record.target()).into());
+        object.insert(String::from("message"), record.args().to_string().into());
+        object.extend(fields(record));
+
+        let mut line = Value::Object(object).to_string();
+        line.push('\n');
+        // One write per record, so the rotating file never splits a line.
+        let _ = self.output.lock().unwrap().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+// Copies key-values into the JSON object, keeping numbers and booleans as such.
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            Value::from(number)
+        } else if let Some(number) = value.to_i64() {
+            Value::from(number)
+        } else if let Some(flag) = value.to_bool() {
+            Value::from(flag)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+// Appends to <dir>/wol_service.log and moves it to wol_service.1.log, wol_service.2.log, ...
+// once it gets too big or too old, deleting the oldest beyond `max_files`.
+pub struct RotatingFile {
+    dir: PathBuf,
+    config: LogConfig,
+    file: Option<File>,
+    size: u64,
+    created: SystemTime,
+    rotate_pending: bool,
+}
+
+impl RotatingFile {
+    pub fn open(config: &LogConfig) -> io::Result<RotatingFile> {
+        let dir = config.dir();
+        fs::create_dir_all(&dir)?;
+        let mut file = RotatingFile {
+            dir,
+            config: config.clone(),
+            file: None,
+            size: 0,
+            created: SystemTime::now(),
+            rotate_pending: false,
+        };
+        file.reopen()?;
+        if file.needs_rotation() {
+            file.rotate()?;
+        }
+        Ok(file)
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        match index {
+            0 => self.dir.join(format!("{}.log", LOG_FILE_NAME)),
+            _ => self.dir.join(format!("{}.{}.log", LOG_FILE_NAME, index)),
+        }
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let path = self.path(0);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        self.size = metadata.len();
+        self.created = metadata.created().or_else(|_| metadata.modified()).unwrap_or_else(|_| SystemTime::now());
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn needs_rotation(&self) -> bool {
+        let too_big = self.config.max_size_mb > 0 && self.size >= self.config.max_size_mb * 1024 * 1024;
+        let too_old = self.config.max_age_days > 0
+            && self.created.elapsed().unwrap_or_default() >= Duration::from_secs(self.config.max_age_days * 24 * 60 * 60);
+        self.size > 0 && (too_big || too_old)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Windows cannot rename a file that is still open.
+        self.file = None;
+        let oldest = self.path(self.config.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for index in (0..self.config.max_files).rev() {
+            let from = self.path(index);
+            if from.exists() {
+                fs::rename(&from, self.path(index + 1))?;
+            }
+        }
+        self.reopen()?;
+        // Windows may hand the new file the old creation time (file system tunneling).
+        self.created = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.rotate_pending {
+            self.rotate_pending = false;
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate the log file: {}", e);
+            }
+        }
+        if self.file.is_none() {
+            self.reopen()?;
+        }
+        let written = self.file.as_mut().unwrap().write(buf)?;
+        self.size += written as u64;
+        // simplelog writes a record in pieces ending with a newline; only rotate between records.
+        if buf[..written].ends_with(b"\n") && self.needs_rotation() {
+            self.rotate_pending = true;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::{io, os::unix::net::UnixDatagram};
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+use super::{field_text, fields, severity};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_IDENTIFIER: &str = "wolstart";
+
+// Sends records to the systemd journal using its native datagram protocol, so the
+// priority and the wake event details survive as separate fields.
+pub struct JournaldLogger {
+    level: LevelFilter,
+    socket: UnixDatagram,
+}
+
+impl JournaldLogger {
+    pub fn connect(level: LevelFilter) -> io::Result<Box<JournaldLogger>> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(Box::new(JournaldLogger { level, socket }))
+    }
+}
+
+// Appends one field; values with newlines need the length-prefixed binary form.
+fn append_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+// Journal field names may only contain uppercase letters, digits and underscores.
+fn field_name(key: &str) -> String {
+    let key: String = key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("WOL_{}", key)
+}
+
+impl Log for JournaldLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut datagram = Vec::new();
+        append_field(&mut datagram, "MESSAGE", &record.args().to_string());
+        append_field(&mut datagram, "PRIORITY", &severity(record.level()).to_string());
+        append_field(&mut datagram, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+        append_field(&mut datagram, "CODE_MODULE", record.module_path().unwrap_or(record.target()));
+        if let Some(file) = record.file() {
+            append_field(&mut datagram, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            append_field(&mut datagram, "CODE_LINE", &line.to_string());
+        }
+        for (key, value) in fields(record) {
+            append_field(&mut datagram, &field_name(&key), &field_text(&value));
+        }
+        // Nowhere left to report a failure to; the terminal logger still has the record.
+        let _ = self.socket.send(&datagram);
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for JournaldLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
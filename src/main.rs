@@ -1,95 +1,620 @@
+mod config;
+mod logging;
+mod monitor;
 mod service;
+mod status;
+#[cfg(unix)]
+mod systemd;
+use std::{
+    ffi::OsString,
+    error::Error,
+    process
+};
+#[cfg(windows)]
 use std::{
     thread::sleep,
     time::{Duration, Instant},
-    ffi::OsString,
-    env
+    env,
 };
-use std::fs::File;
-use log::{error, info, LevelFilter};
-use simplelog::*;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use log::{debug, error, info, warn};
+#[cfg(windows)]
 use windows_service::{
     service::{ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType, ServiceState},
     service_manager::{ServiceManager, ServiceManagerAccess},
 };
+#[cfg(windows)]
 use windows_sys::Win32::Foundation::ERROR_SERVICE_DOES_NOT_EXIST;
 use clap::Command;
+use pnet::util::MacAddr;
+use serde::Serialize;
+use service::{auth, history, wake_event};
+use service::wake_event::{Outcome, WakeRecord};
 
-const DEFAULT_PROGRAM: &str = "kodi.exe";
-const DEFAULT_RUN_PATH: &str = "C:\\Program Files\\Kodi\\kodi.exe";
-const DEFAULT_HOST_IP: &str = "192.168.1.132";
-const DEFAULT_LOG_LEVEL: &str = "warn";
-fn main() -> windows_service::Result<()> {
+fn main() -> Result<(), Box<dyn Error>> {
+    // The service runs itself again inside a systemd scope to finish starting a program there.
+    #[cfg(unix)]
+    {
+        let arguments: Vec<String> = std::env::args().skip(1).collect();
+        if arguments.first().map(String::as_str) == Some(service::ISOLATE) {
+            eprintln!("{}", service::isolate(&arguments[1..]));
+            process::exit(1);
+        }
+    }
     // Parse args
-    let log_file = File::create("wol_service.log").unwrap();
     let cmd = Command::new("WakeOnLan Start")
-        .subcommand(
+        .arg(
+            clap::arg!(--"log-dir" <DIR>)
+                .help("Directory for the log files")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            clap::arg!(--"terminal-log-level" <LEVEL>)
+                .help("Logging level for the terminal")
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            clap::arg!(--"terminal-log-format" <FORMAT>)
+                .help("Log format for the terminal: text or json")
+                .value_parser(|s: &str| s.parse::<logging::LogFormat>())
+                .global(true)
+                .required(false),
+        )
+        .arg(
+            clap::arg!(--"dry-run")
+                .help("Show what would be done without starting programs or changing the system")
+                .global(true),
+        )
+        .subcommand(platform_install_args(
             Command::new("install")
                 .about("Installs the service")
                 .arg(
                     clap::arg!(--"program" <PROGRAM>)
                         .help("Program to use")
-                        .default_value(DEFAULT_PROGRAM),
+                        .required(false),
                 )
                 .arg(
                     clap::arg!(--"run-path" <RUN_PATH>)
                         .help("Path to run the program from")
-                        .default_value(DEFAULT_RUN_PATH),
+                        .required(false),
                 )
                 .arg(
                     clap::arg!(--"host-ip" <HOST_IP>)
                         .help("IP address of the host")
-                        .default_value(DEFAULT_HOST_IP),
+                        .required(false),
                 )
                 .arg(
                     clap::arg!(--"log-level" <LOG_LEVEL>)
                         .help("Logging level")
-                        .default_value(DEFAULT_LOG_LEVEL),
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"config" <CONFIG>)
+                        .help("Path to a TOML config file with source filters")
+                        .required(false),
                 ),
-            )
-        .subcommand(Command::new("uninstall").about("Uninstalls the service")
+            ))
+        .subcommand(platform_uninstall_args(Command::new("uninstall").about("Uninstalls the service")
                         .arg(
                             clap::arg!(--"log-level" <LOG_LEVEL>)
                                 .help("Logging level")
-                                .default_value(DEFAULT_LOG_LEVEL),
+                                .required(false),
+                        ),
+        ))
+        .subcommand(
+            Command::new("send")
+                .about("Sends a magic packet, optionally authenticated")
+                .arg(
+                    clap::arg!(--"mac" <MAC>)
+                        .help("MAC address of the machine to wake")
+                        .required(true),
+                )
+                .arg(
+                    clap::arg!(--"address" <ADDRESS>)
+                        .help("Destination address")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"port" <PORT>)
+                        .help("Destination UDP port")
+                        .value_parser(clap::value_parser!(u16))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"key" <KEY>)
+                        .help("Shared key for an authenticated packet")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"config" <CONFIG>)
+                        .help("Config file to take the shared key from")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Shows recorded wake events")
+                .arg(
+                    clap::arg!(--"config" <CONFIG>)
+                        .help("Config file to take the history location from instead of the installed service's")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"since" <TIME>)
+                        .help("Only events at or after this time (YYYY-MM-DD, YYYY-MM-DD HH:MM or RFC 3339)")
+                        .value_parser(history::parse_time)
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"until" <TIME>)
+                        .help("Only events before this time")
+                        .value_parser(history::parse_time)
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"mac" <MAC>)
+                        .help("Only events for this target MAC")
+                        .value_parser(clap::value_parser!(MacAddr))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"source" <SOURCE>)
+                        .help("Only events sent from this MAC, IP address or CIDR block")
+                        .value_parser(|s: &str| s.parse::<history::Source>())
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"outcome" <OUTCOME>)
+                        .help("Only events with this outcome")
+                        .value_parser(|s: &str| s.parse::<Outcome>())
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"limit" <LIMIT>)
+                        .help("Only the most recent LIMIT events")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"json")
+                        .help("Print JSON instead of a table"),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Shows whether the service is installed and running and what it is doing")
+                .arg(
+                    clap::arg!(--"config" <CONFIG>)
+                        .help("Config file to use instead of the installed one")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"json")
+                        .help("Print JSON instead of text"),
+                ),
+        )
+        .subcommand(
+            Command::new("interfaces")
+                .about("Lists the network interfaces and which one the service captures on")
+                .arg(
+                    clap::arg!(--"host-ip" <HOST_IP>)
+                        .help("Host IP prefix to match instead of the installed one")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"json")
+                        .help("Print JSON instead of a table"),
+                ),
+        )
+        .subcommand(
+            Command::new("monitor")
+                .about("Prints the magic packets seen and what the service would do, without starting anything")
+                .arg(
+                    clap::arg!(--"config" <CONFIG>)
+                        .help("Config file to use instead of the installed one")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"host-ip" <HOST_IP>)
+                        .help("Host IP prefix of the interface instead of the installed one")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Checks or prints a config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("validate")
+                        .about("Checks a config file against this machine and exits non-zero on errors")
+                        .arg(
+                            clap::arg!(--"config" <CONFIG>)
+                                .help("Config file to check instead of the installed one")
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(--"host-ip" <HOST_IP>)
+                                .help("Host IP prefix to check instead of the installed one")
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Prints the effective config including defaults")
+                        .arg(
+                            clap::arg!(--"config" <CONFIG>)
+                                .help("Config file to print instead of the installed one")
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(--"sources")
+                                .help("Print the layered settings and where each value comes from instead"),
                         ),
+                ),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Runs the service in the foreground until Ctrl-C, logging to the terminal")
+                .arg(
+                    clap::arg!(--"program" <PROGRAM>)
+                        .help("Program to use")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"run-path" <RUN_PATH>)
+                        .help("Path to run the program from")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"host-ip" <HOST_IP>)
+                        .help("IP address of the host")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"log-level" <LOG_LEVEL>)
+                        .help("Logging level")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"config" <CONFIG>)
+                        .help("Path to a TOML config file")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("reload")
+                .about("Asks the running service to reload its config file"),
+        )
+        .subcommand(
+            Command::new("queue")
+                .about("Lists or cancels the launches waiting for somebody to log on")
+                .arg(
+                    clap::arg!(--"cancel")
+                        .help("Cancel the queued launches instead of listing them"),
+                )
+                .arg(
+                    clap::arg!(--"rule" <RULE>)
+                        .help("Only cancel the launch queued for this rule")
+                        .requires("cancel")
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"json")
+                        .help("Print JSON instead of a table"),
+                ),
         )
         .allow_external_subcommands(true);
 
     // Initialize logging
     let matches = cmd.get_matches();
-    // Extract log level early, and set a default if it doesn't exist
-    let log_level = if let Some(matches) = matches.subcommand_matches("install")
-        .or_else(|| matches.subcommand_matches("uninstall")) {
-        match matches.get_one::<String>("log-level").unwrap_or(&String::from("info")).as_str() {
-            "trace" => LevelFilter::Trace,
-            "debug" => LevelFilter::Debug,
-            "warn"=> LevelFilter::Warn,
-            "error" => LevelFilter::Error,
-            _ => LevelFilter::Info,
-        }
-    } else {
-        LevelFilter::Info // Default level if no subcommand or something goes wrong
+    let settings = config::layers::Settings::load(&matches);
+    // Extract log level and config file early, the latter may hold the [log] section
+    let (log_level, config_path) = match matches.subcommand() {
+        Some(("install", sub_matches)) | Some(("uninstall", sub_matches)) | Some(("run", sub_matches)) => (
+            Some(settings.log_level.value.clone()),
+            sub_matches.try_get_one::<String>("config").ok().flatten().cloned(),
+        ),
+        Some(("send", sub_matches)) | Some(("history", sub_matches)) | Some(("status", sub_matches))
+        | Some(("monitor", sub_matches)) => (
+            None,
+            sub_matches.get_one::<String>("config").cloned(),
+        ),
+        Some(("config", sub_matches)) => (
+            None,
+            sub_matches.subcommand().and_then(|(_, matches)| matches.get_one::<String>("config").cloned()),
+        ),
+        Some(("interfaces", _)) | Some(("reload", _)) | Some(("queue", _)) => (None, None),
+        Some((_, service_matches)) => {
+            // The service is started as `WolStart.exe <program> <run path> <host ip> <log level> [config]`.
+            let arguments: Vec<String> = service_matches.get_many::<OsString>("").into_iter().flatten()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
+            (arguments.get(2).cloned(), arguments.get(3).cloned())
+        }
+        None => (None, None),
+    };
+    // Errors in the config file are reported by the subcommand itself once logging is up.
+    let log_config = config_path.and_then(|path| config::Config::load(&path).ok())
+        .map(|config| config.log)
+        .unwrap_or_default();
+    let log_config = logging::LogConfig {
+        dir: matches.get_one::<String>("log-dir").map(PathBuf::from).or(log_config.dir),
+        terminal_format: matches.get_one::<logging::LogFormat>("terminal-log-format").copied()
+            .unwrap_or(log_config.terminal_format),
+        ..log_config
     };
-    CombinedLogger::init(
-        vec![
-            TermLogger::new(LevelFilter::Warn, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-            WriteLogger::new(log_level, Config::default(), log_file)
-        ]
-    ).unwrap();
-    info!("{:?}", matches.subcommand());
+    let terminal_level = match (matches.get_one::<String>("terminal-log-level"), matches.subcommand_name()) {
+        (Some(level), _) => level.as_str(),
+        // In the foreground the terminal is where the service's log is watched, wake events included.
+        (None, Some("run")) if settings.log_level.source == config::layers::Source::Default => "info",
+        (None, Some("run")) => settings.log_level.value.as_str(),
+        (None, _) => log_config.terminal_level.as_str(),
+    };
+    logging::init(&log_config, logging::parse_level(terminal_level),
+                  logging::parse_level(log_level.as_deref().unwrap_or("info")));
+    debug!("Running {}", matches.subcommand_name().unwrap_or("without a subcommand"));
+    for warning in &settings.warnings {
+        warn!("{}", warning);
+    }
     match matches.subcommand() {
         Some(("install", install_matches)) => {
             info!("Install...");
-            let program = install_matches.get_one::<String>("program").unwrap();
-            let run_path = install_matches.get_one::<String>("run-path").unwrap();
-            let host_ip = install_matches.get_one::<String>("host-ip").unwrap();
-            install(&program, &run_path, &host_ip,
-                    &install_matches.get_one::<String>("log-level").unwrap())?;
+            // The service runs from System32, so store the config file as an absolute path.
+            let config_path = match install_matches.get_one::<String>("config") {
+                Some(path) => match std::fs::canonicalize(path) {
+                    Ok(path) => Some(path.to_string_lossy().into_owned()),
+                    Err(e) => {
+                        error!("Config file {} not found: {}", path, e);
+                        process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            // The systemd unit allows what the rules need.
+            #[cfg_attr(windows, allow(unused_variables))]
+            let config = match &config_path {
+                Some(path) => match config::Config::load(path) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        error!("{}", e);
+                        process::exit(1);
+                    }
+                },
+                None => config::Config::default(),
+            };
+            #[cfg(windows)]
+            install(&settings.program.value, &settings.run_path.value, &settings.host_ip.value,
+                    &settings.log_level.value, config_path.as_ref(), install_matches.get_flag("dry-run"))?;
+            #[cfg(unix)]
+            {
+                let mut arguments = vec![settings.program.value.clone(), settings.run_path.value.clone(),
+                                         settings.host_ip.value.clone(), settings.log_level.value.clone()];
+                arguments.extend(config_path);
+                if let Err(e) = systemd::install(&arguments, &config, install_matches) {
+                    error!("{}", e);
+                    process::exit(1);
+                }
+            }
         }
-        Some(("uninstall", _)) => {
+        // The options are systemd's; the SCM only needs --dry-run.
+        Some(("uninstall", _uninstall_matches)) => {
             info!("Uninstall...");
-            uninstall()?;
+            #[cfg(windows)]
+            if _uninstall_matches.get_flag("dry-run") {
+                println!("Would stop and delete {}", service::SERVICE_NAME);
+            } else {
+                uninstall()?;
+            }
+            #[cfg(unix)]
+            if let Err(e) = systemd::uninstall(_uninstall_matches) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(("send", send_matches)) => {
+            info!("Send...");
+            // An explicit key wins over the one in the config file.
+            let key = match send_matches.get_one::<String>("key") {
+                Some(key) => Some(key.clone()),
+                None => match send_matches.get_one::<String>("config") {
+                    Some(path) => match config::Config::load(path) {
+                        Ok(config) => config.auth.map(|auth| auth.key),
+                        Err(e) => {
+                            error!("{}", e);
+                            return Ok(());
+                        }
+                    },
+                    None => None,
+                },
+            };
+            if let Err(e) = send(send_matches.get_one::<String>("mac").unwrap(),
+                                 &settings.send_address.value,
+                                 settings.send_port.value,
+                                 key.as_deref(),
+                                 send_matches.get_flag("dry-run")) {
+                error!("Failed to send magic packet: {}", e);
+            }
+        }
+        Some(("history", history_matches)) => {
+            let history_config = match history_matches.get_one::<String>("config") {
+                Some(path) => match config::Config::load(path) {
+                    Ok(config) => config.history,
+                    Err(e) => {
+                        error!("{}", e);
+                        process::exit(1);
+                    }
+                },
+                None => installed_config().history,
+            };
+            let query = history::HistoryQuery {
+                since: history_matches.get_one("since").copied(),
+                until: history_matches.get_one("until").copied(),
+                target_mac: history_matches.get_one("mac").copied(),
+                source: history_matches.get_one("source").copied(),
+                outcome: history_matches.get_one("outcome").copied(),
+            };
+            if let Err(e) = show_history(&history_config.path(), &query,
+                                         history_matches.get_one::<usize>("limit").copied(),
+                                         history_matches.get_flag("json")) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(("status", status_matches)) => {
+            let report = status::collect(status_matches.get_one::<String>("config").map(String::as_str),
+                                         [&settings.program.value, &settings.run_path.value, &settings.host_ip.value]);
+            if status_matches.get_flag("json") {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => error!("{}", e),
+                }
+            } else {
+                status::print(&report);
+            }
+        }
+        Some(("interfaces", interfaces_matches)) => {
+            // Match against what the installed service was given, unless told otherwise.
+            let host_ip = interfaces_matches.get_one::<String>("host-ip").cloned()
+                .unwrap_or_else(|| installed_config().host_ip(&installed_host_ip(&settings)));
+            if let Err(e) = show_interfaces(&host_ip, interfaces_matches.get_flag("json")) {
+                error!("{}", e);
+            }
+        }
+        Some(("monitor", monitor_matches)) => {
+            // Watch with the installed settings, unless told otherwise.
+            let config = match monitor_matches.get_one::<String>("config").cloned().or_else(|| installed_argument(4)) {
+                Some(path) => match config::Config::load(&path) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        error!("{}", e);
+                        return Ok(());
+                    }
+                },
+                None => config::Config::default(),
+            };
+            let host_ip = monitor_matches.get_one::<String>("host-ip").cloned()
+                .unwrap_or_else(|| config.host_ip(&installed_host_ip(&settings)));
+            match service::select_interface(&host_ip) {
+                Some(interface) => {
+                    if let Err(e) = monitor::run(&interface, &config, &config.default_action(&installed_action(&settings))) {
+                        error!("{}", e);
+                    }
+                },
+                None => error!("No interface has an IP address starting with {}", host_ip),
+            }
+        }
+        Some(("config", config_matches)) => {
+            let (action, action_matches) = config_matches.subcommand().unwrap();
+            let path = action_matches.get_one::<String>("config").cloned().or_else(|| installed_argument(4));
+            let config = match &path {
+                Some(path) => match config::Config::load(path) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        println!("error: {}", e);
+                        process::exit(1);
+                    }
+                },
+                None => config::Config::default(),
+            };
+            match action {
+                "validate" => {
+                    let host_ip = action_matches.get_one::<String>("host-ip").cloned()
+                        .unwrap_or_else(|| config.host_ip(&installed_host_ip(&settings)));
+                    let diagnostics = config::lint::lint(&config, &host_ip, &config.default_action(&installed_action(&settings)));
+                    for diagnostic in &diagnostics {
+                        println!("{}", diagnostic);
+                    }
+                    let errors = diagnostics.iter()
+                        .filter(|diagnostic| diagnostic.severity == config::lint::Severity::Error)
+                        .count();
+                    println!("{}: {} errors, {} warnings", path.as_deref().unwrap_or("default config"),
+                             errors, diagnostics.len() - errors);
+                    if errors > 0 {
+                        process::exit(1);
+                    }
+                },
+                _ if action_matches.get_flag("sources") => {
+                    let mut settings = settings;
+                    if let Some(installation) = status::query_installation().ok().flatten() {
+                        settings.apply_installed(&installation.arguments);
+                    }
+                    if let Some(path) = &path {
+                        settings.apply_service_section(&config.service, path);
+                    }
+                    for line in settings.describe() {
+                        println!("{}", line);
+                    }
+                },
+                _ => match toml::to_string(&config.effective(&installed_action(&settings), &installed_host_ip(&settings))) {
+                    Ok(toml) => print!("{}", toml),
+                    Err(e) => error!("Failed to print the config: {}", e),
+                },
+            }
+        }
+        Some(("reload", _)) => {
+            // Through the API the outcome comes back; a service control only lands in the service log.
+            let result = match &installed_config().api {
+                Some(api_config) => service::api::request::<serde_json::Value>(api_config, "POST", "/reload")
+                    .map(|body| body["result"].as_str().unwrap_or("Reloaded").to_string()),
+                None => request_reload()
+                    .map(|()| format!("Asked {} to reload, the result is in its log", service::SERVICE_NAME)),
+            };
+            match result {
+                Ok(message) => println!("{}", message),
+                Err(e) => error!("{}", e),
+            }
+        }
+        Some(("queue", queue_matches)) => {
+            // The queue only lives in the running service.
+            let Some(api_config) = installed_config().api else {
+                error!("The queue is only available through the API; add an [api] section to the config");
+                process::exit(1);
+            };
+            if queue_matches.get_flag("cancel") {
+                let path = match queue_matches.get_one::<String>("rule") {
+                    Some(rule) => format!("/queue/{}", rule),
+                    None => String::from("/queue"),
+                };
+                let result = service::api::request::<serde_json::Value>(&api_config, "DELETE", &path)
+                    .map(|body| println!("{}", body["result"].as_str().unwrap_or("Cancelled")));
+                if let Err(e) = result {
+                    error!("{}", e);
+                    process::exit(1);
+                }
+            } else {
+                let result = match service::api::request::<Vec<service::queue::QueuedLaunch>>(&api_config, "GET", "/queue") {
+                    Ok(queue) if queue_matches.get_flag("json") => serde_json::to_string_pretty(&queue)
+                        .map(|json| println!("{}", json))
+                        .map_err(|e| e.to_string()),
+                    Ok(queue) => {
+                        print_queue(&queue);
+                        Ok(())
+                    },
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    error!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Some(("run", run_matches)) => {
+            // The launch arguments `install` would give the installed service.
+            let mut arguments = vec![settings.program.value.clone(), settings.run_path.value.clone(),
+                                     settings.host_ip.value.clone(), settings.log_level.value.clone()];
+            arguments.extend(run_matches.get_one::<String>("config").cloned());
+            let dry_run = run_matches.get_flag("dry-run");
+            if dry_run {
+                info!("Dry run: programs are recorded in the history instead of being started");
+            }
+            info!("Running in the foreground, press Ctrl-C to stop");
+            // Failures have been logged already.
+            if service::foreground::run(&arguments, dry_run).is_err() {
+                process::exit(1);
+            }
         }
         Some(_) => {
             info!("Run service");
@@ -103,14 +628,36 @@ fn main() -> windows_service::Result<()> {
     Ok(())
 }
 
-fn install(prg: &String, run_path: &String, host_ip: &String, log_level: &String) -> windows_service::Result<()> {
-    let manager_access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
-    let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)?;
+// Linux installs a systemd unit, which takes a few more options.
+fn platform_install_args(command: Command) -> Command {
+    #[cfg(unix)]
+    let command = systemd::install_args(command);
+    command
+}
+
+fn platform_uninstall_args(command: Command) -> Command {
+    #[cfg(unix)]
+    let command = systemd::uninstall_args(command);
+    command
+}
 
+#[cfg(windows)]
+fn install(prg: &String, run_path: &String, host_ip: &String, log_level: &String,
+           config_path: Option<&String>, dry_run: bool) -> windows_service::Result<()> {
     let service_binary_path = env::current_exe()
         .unwrap()
         .with_file_name("WolStart.exe");
 
+    let mut launch_arguments = vec![
+        OsString::from(prg),
+        OsString::from(run_path),
+        OsString::from(host_ip),
+        OsString::from(log_level),
+    ];
+    if let Some(config_path) = config_path {
+        launch_arguments.push(OsString::from(config_path));
+    }
+
     let service_info = ServiceInfo {
         name: OsString::from("wol_service"),
         display_name: OsString::from("WakeOnLan service"),
@@ -118,21 +665,200 @@ fn install(prg: &String, run_path: &String, host_ip: &String, log_level: &String
         start_type: ServiceStartType::AutoStart,
         executable_path: service_binary_path,
         error_control: ServiceErrorControl::Normal,
-        launch_arguments: vec![
-            OsString::from(prg),
-            OsString::from(run_path),
-            OsString::from(host_ip),
-            OsString::from(log_level),
-        ],
+        launch_arguments,
         dependencies: vec![],
         account_name: None,
         account_password: None,
     };
+    if dry_run {
+        println!("Would create {} ({}) running {} with arguments {:?}",
+                 service::SERVICE_NAME, service_info.display_name.to_string_lossy(),
+                 service_info.executable_path.display(), service_info.launch_arguments);
+        return Ok(());
+    }
+
+    let manager_access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
+    let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)?;
     let service = service_manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG)?;
     service.set_description("Windows service to run program on receiving wake on lan packet")?;
     Ok(())
 }
 
+fn send(mac: &str, address: &str, port: u16, key: Option<&str>, dry_run: bool) -> Result<(), String> {
+    let target_mac: MacAddr = mac.parse().map_err(|e| format!("Invalid MAC address {}: {:?}", mac, e))?;
+    let packet = match key {
+        Some(key) => auth::signed_magic_packet(key.as_bytes(), target_mac),
+        None => wake_event::magic_packet(target_mac),
+    };
+    if dry_run {
+        println!("Would send {} magic packet of {} bytes for {} to {}:{}",
+                 if key.is_some() { "authenticated" } else { "plain" }, packet.len(), target_mac, address, port);
+        return Ok(());
+    }
+
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    socket.send_to(&packet, (address, port)).map_err(|e| e.to_string())?;
+    println!("Sent {} magic packet for {} to {}:{}",
+             if key.is_some() { "authenticated" } else { "plain" }, target_mac, address, port);
+    Ok(())
+}
+
+fn show_history(path: &Path, query: &history::HistoryQuery, limit: Option<usize>, json: bool) -> Result<(), String> {
+    let mut records: Vec<WakeRecord> = history::read(path)?.into_iter()
+        .filter(|record| query.matches(record))
+        .collect();
+    if let Some(limit) = limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    println!("{:<19}  {:<17}  {:<21}  {:<12}  {:<15}  {:>6}  DETAIL", "TIME", "TARGET", "SOURCE", "RULE", "OUTCOME", "PID");
+    for record in &records {
+        println!("{:<19}  {:<17}  {:<21}  {:<12}  {:<15}  {:>6}  {}",
+                 record.time.format("%Y-%m-%d %H:%M:%S"),
+                 record.target_mac.to_string(),
+                 record.source(),
+                 record.rule.as_deref().unwrap_or("-"),
+                 record.outcome.to_string(),
+                 record.pid.map_or(String::from("-"), |pid| pid.to_string()),
+                 record.detail.as_deref().or(record.action.as_deref()).unwrap_or(""));
+    }
+    Ok(())
+}
+
+// A launch argument of the installed service: program, run path, host ip, log level, config file.
+fn installed_argument(index: usize) -> Option<String> {
+    status::query_installation().ok().flatten()
+        .and_then(|installation| installation.arguments.get(index).cloned())
+}
+
+fn installed_host_ip(settings: &config::layers::Settings) -> String {
+    installed_argument(2).unwrap_or_else(|| settings.host_ip.value.clone())
+}
+
+// The installed service's config file, or the defaults if it has none or it cannot be read.
+fn installed_config() -> config::Config {
+    installed_argument(4).and_then(|path| config::Config::load(&path).ok()).unwrap_or_default()
+}
+
+// The program the installed service was given; the config's [service] section may override it.
+fn installed_action(settings: &config::layers::Settings) -> service::rules::Action {
+    service::rules::Action::new(&installed_argument(0).unwrap_or_else(|| settings.program.value.clone()),
+                                &installed_argument(1).unwrap_or_else(|| settings.run_path.value.clone()))
+}
+
+#[derive(Serialize)]
+struct InterfaceInfo {
+    name: String,
+    description: String,
+    mac: Option<MacAddr>,
+    // Addresses with their prefix length, e.g. 192.168.1.132/24.
+    ips: Vec<String>,
+    up: bool,
+    loopback: bool,
+    broadcast: bool,
+    // Whether the service would capture on this interface.
+    selected: bool,
+}
+
+fn show_interfaces(host_ip: &str, json: bool) -> Result<(), String> {
+    let selected = service::select_interface(host_ip).map(|interface| interface.name);
+    let interfaces: Vec<InterfaceInfo> = pnet::datalink::interfaces().into_iter()
+        .map(|interface| InterfaceInfo {
+            selected: selected.as_deref() == Some(interface.name.as_str()),
+            ips: interface.ips.iter().map(|ip| ip.to_string()).collect(),
+            up: interface.is_up(),
+            loopback: interface.is_loopback(),
+            broadcast: interface.is_broadcast(),
+            mac: interface.mac,
+            name: interface.name,
+            description: interface.description,
+        })
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&interfaces).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    for interface in &interfaces {
+        let mut flags = Vec::new();
+        if interface.up {
+            flags.push("up");
+        }
+        if interface.loopback {
+            flags.push("loopback");
+        }
+        if interface.broadcast {
+            flags.push("broadcast");
+        }
+        println!("{}{}", if interface.selected { "* " } else { "  " }, interface.name);
+        if !interface.description.is_empty() {
+            println!("    description: {}", interface.description);
+        }
+        println!("    mac:         {}", interface.mac.map_or(String::from("-"), |mac| mac.to_string()));
+        println!("    ips:         {}", if interface.ips.is_empty() { String::from("-") } else { interface.ips.join(", ") });
+        println!("    flags:       {}", flags.join(", "));
+    }
+    match selected {
+        Some(name) => println!("\n* selected by host IP {}: {}", host_ip, name),
+        None => println!("\nNo interface matches host IP {}", host_ip),
+    }
+    Ok(())
+}
+
+fn print_queue(queue: &[service::queue::QueuedLaunch]) {
+    if queue.is_empty() {
+        println!("No launches are waiting for a user session");
+        return;
+    }
+    println!("{:<19}  {:<19}  {:<12}  ACTION", "QUEUED", "EXPIRES", "RULE");
+    for launch in queue {
+        println!("{:<19}  {:<19}  {:<12}  {}", launch.queued_at.format("%Y-%m-%d %H:%M:%S"),
+                 launch.expires_at.format("%Y-%m-%d %H:%M:%S"), launch.rule, launch.action);
+    }
+}
+
+// Sends SERVICE_CONTROL_PARAMCHANGE, which windows-service has no call for.
+#[cfg(windows)]
+fn request_reload() -> Result<(), String> {
+    use std::{ffi::OsStr, io, iter, os::windows::ffi::OsStrExt, ptr};
+    use winapi::um::winsvc::{CloseServiceHandle, ControlService, OpenSCManagerW, OpenServiceW,
+                             SC_MANAGER_CONNECT, SERVICE_CONTROL_PARAMCHANGE, SERVICE_PAUSE_CONTINUE, SERVICE_STATUS};
+
+    let name: Vec<u16> = OsStr::new(service::SERVICE_NAME).encode_wide().chain(iter::once(0)).collect();
+    unsafe {
+        let manager = OpenSCManagerW(ptr::null(), ptr::null(), SC_MANAGER_CONNECT);
+        if manager.is_null() {
+            return Err(format!("Failed to open the service manager: {}", io::Error::last_os_error()));
+        }
+        let service = OpenServiceW(manager, name.as_ptr(), SERVICE_PAUSE_CONTINUE);
+        let result = if service.is_null() {
+            Err(format!("Failed to open {}: {}", service::SERVICE_NAME, io::Error::last_os_error()))
+        } else {
+            let mut status: SERVICE_STATUS = std::mem::zeroed();
+            let result = if ControlService(service, SERVICE_CONTROL_PARAMCHANGE, &mut status) == 0 {
+                Err(format!("Failed to signal {}: {}", service::SERVICE_NAME, io::Error::last_os_error()))
+            } else {
+                Ok(())
+            };
+            CloseServiceHandle(service);
+            result
+        };
+        CloseServiceHandle(manager);
+        result
+    }
+}
+
+#[cfg(unix)]
+fn request_reload() -> Result<(), String> {
+    systemd::reload()
+}
+
+#[cfg(windows)]
 fn uninstall() -> windows_service::Result<()> {
     let manager_access = ServiceManagerAccess::CONNECT;
     let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)?;
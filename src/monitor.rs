@@ -0,0 +1,73 @@
+use std::{io, time::Duration};
+use pnet::datalink::{self, NetworkInterface};
+use crate::config::Config;
+use crate::service::{self, auth::{Authenticator, TRAILER_LEN}, metrics::RejectReason, rules::{self, Action, Decision, Mode, Rule}};
+use crate::service::wake_event::WakeEvent;
+
+// Captures on `interface` and prints every magic packet together with what the service
+// would do with it, without starting anything. Runs until interrupted.
+pub fn run(interface: &NetworkInterface, config: &Config, default_action: &Action) -> Result<(), String> {
+    let rules = config.effective_rules();
+    let mut authenticator = config.auth.as_ref().map(Authenticator::new);
+    let channel_config = datalink::Config { read_timeout: Some(Duration::from_secs(1)), ..Default::default() };
+    let mut rx = match datalink::channel(interface, channel_config) {
+        Ok(datalink::Channel::Ethernet(_, rx)) => rx,
+        Ok(_) => return Err(String::from("Failed to create datalink channel")),
+        Err(e) => return Err(format!("Failed to capture on {}: {}", interface.name, e)),
+    };
+
+    println!("Watching {} ({}) for magic packets, press Ctrl-C to stop", interface.name, interface.description);
+    loop {
+        match rx.next() {
+            Ok(frame) => {
+                if let Some(event) = WakeEvent::from_frame(&interface.name, frame) {
+                    report(&event, &interface.description, config, authenticator.as_mut(), &rules, default_action);
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => eprintln!("An error occurred while reading packet: {}", e),
+        }
+    }
+}
+
+fn report(event: &WakeEvent, interface_description: &str, config: &Config, authenticator: Option<&mut Authenticator>,
+          rules: &[Rule], default_action: &Action) {
+    println!();
+    println!("{}  {}", event.received_at.format("%Y-%m-%d %H:%M:%S"), event);
+    println!("    encapsulation: {}", event.encapsulation);
+    let trailer = match (event.password(), event.trailer.len()) {
+        (Some(password), _) => format!("SecureOn password {}", password),
+        (None, 0) => String::from("none"),
+        (None, len) if len >= TRAILER_LEN => String::from("authentication trailer"),
+        (None, len) => format!("{} unrecognized bytes", len),
+    };
+    println!("    trailer:       {}", trailer);
+    let screening = service::screen(config, authenticator, event, interface_description);
+    match &screening {
+        Ok(()) => println!("    screening:     passed"),
+        Err((RejectReason::Auth, reason)) => println!("    screening:     rejected by authentication: {}", reason),
+        Err((_, reason)) => println!("    screening:     rejected by source filter: {}", reason),
+    }
+    for (name, explanation) in rules::explain(rules, event) {
+        println!("    rule {}: {}", name, explanation);
+    }
+
+    let verdict = if screening.is_err() {
+        String::from("ignored")
+    } else {
+        match rules::evaluate(rules, event, default_action) {
+            Decision::Run(rule, action) | Decision::OutOfSchedule(rule, _, Some(action)) => {
+                match (rule.mode, service::is_program_running(&action, None)) {
+                    (Mode::Start, true) =>
+                        format!("ignored, {} is already running: found a process {}", action.program, action.describe_running()),
+                    (Mode::Toggle | Mode::Stop, true) => format!("would stop {}", action.program),
+                    (Mode::Stop, false) => format!("ignored, {} is not running", action.program),
+                    (Mode::Start | Mode::Toggle, false) => format!("would start {}", action.run_path),
+                }
+            },
+            Decision::OutOfSchedule(rule, _, None) => format!("ignored, outside the schedule of rule {}", rule.name),
+            Decision::NoMatch => String::from("ignored, no rule matches"),
+        }
+    };
+    println!("    verdict:       {}", verdict);
+}
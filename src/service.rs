@@ -1,131 +1,459 @@
+#[cfg(unix)]
+mod daemon;
+#[cfg(windows)]
 mod run_as_current_user;
+#[cfg(windows)]
+mod scm;
+pub mod api;
+pub mod auth;
+pub mod control;
+pub mod filter;
+pub mod foreground;
+pub mod history;
+pub mod limits;
+pub mod metrics;
+pub mod process_match;
+pub mod queue;
+pub mod readiness;
+pub mod rules;
+pub mod schedule;
+pub mod session;
+pub mod state;
+pub mod stop;
+pub mod supervisor;
+pub mod wake_event;
 use pnet::datalink::{self, NetworkInterface};
-use sysinfo::{System, SystemExt};
+use sysinfo::{Pid, PidExt, System, SystemExt};
 use log::{error, info, warn, debug};
-use std::{sync::{
-    Arc, Mutex, mpsc::{self, TryRecvError, Receiver}
-    }, ffi::OsString, time::Duration, env};
-use windows_service::{
-    define_windows_service,
-    service::{
-        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
-        ServiceType,
-    },
-    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
-    service_dispatcher, Result,
-};
-
-const SERVICE_NAME: &str = "wol_service";
-const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
-pub fn run() -> Result<()> {
-    info!("Starting the service...");
-    // Register generated `ffi_service_main` with the system and start the service, blocking
-    // this thread until the service is stopped.
-    match service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
-        Ok(_) => {
-            info!("Service stopped successfully.");
-            Ok(())
+use std::{collections::{HashMap, HashSet}, sync::{
+    Arc, Mutex, mpsc::{TryRecvError, Receiver, Sender}
+    }, time::{Duration, Instant}, io};
+use crate::config::Config;
+use api::Api;
+use auth::Authenticator;
+use control::Control;
+use history::History;
+use metrics::{Metrics, MetricsServer, RejectReason};
+use queue::{Queue, QueuedLaunch};
+use readiness::{OnFailure, Pending};
+use rules::{Action, Decision, Mode, Rule};
+use state::{Activity, ProgramStatus, SharedState, State};
+use supervisor::{Process, Supervisor};
+use wake_event::{Outcome, WakeEvent, WakeRecord};
+
+pub const SERVICE_NAME: &str = "wol_service";
+// Senders often repeat a magic packet; wakes for a toggle or stop rule this soon after
+// the last one it acted on are ignored so they do not undo it.
+const REPEAT_WINDOW: Duration = Duration::from_secs(5);
+#[cfg(windows)]
+pub use scm::run;
+#[cfg(unix)]
+pub use daemon::run;
+#[cfg(windows)]
+use scm::{report_stopped, StatusHandle};
+#[cfg(unix)]
+use daemon::{report_stopped, StatusHandle};
+
+// Runs the service from its launch arguments: <program> <run path> <host ip> <log level> [config].
+fn run_from_arguments(arguments: &[String], dry_run: bool, shutdown_rx: Arc<Mutex<Receiver<()>>>,
+                      controls: (Sender<Control>, Receiver<Control>),
+                      status_handle: Option<StatusHandle>) -> Result<(), String> {
+    // Ensure we have at least program, run path and host ip.
+    if arguments.len() < 3 {
+        let message = String::from("Insufficient arguments provided to the service");
+        report_stopped(&status_handle, &message, 1);
+        return Err(message);
+    }
+
+    let prg = arguments[0].as_str();
+    let run_path = arguments[1].as_str();
+    let host_ip = arguments[2].as_str();
+    // arguments[3] is the log level; the optional fifth argument is the config file.
+    let config = match arguments.get(4) {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                report_stopped(&status_handle, &e, 1);
+                return Err(e);
+            }
         },
-        Err(e) => {
-            error!("Service stopped with error: {:?}", e);
-            Err(e)
+        None => Config::default(),
+    };
+
+    run_service((Action::new(prg, run_path), host_ip.to_string()), dry_run, config, arguments.get(4).cloned(),
+                shutdown_rx, controls, status_handle)
+}
+
+// Everything the worker loop needs to act on wake events; replaced as a whole on reload.
+struct Context {
+    config: Config,
+    config_path: Option<String>,
+    // Program, run path and host ip from the launch arguments, before the config overrides them.
+    installed: (Action, String),
+    // From --dry-run: record what would be started instead of starting it.
+    dry_run: bool,
+    default_action: Action,
+    interface: NetworkInterface,
+    // Set when a reload selected another interface, so the capture channel is re-opened.
+    reopen_capture: bool,
+    rules: Vec<Rule>,
+    authenticator: Option<Authenticator>,
+    state: SharedState,
+    metrics: Arc<Metrics>,
+    history: Option<Mutex<History>>,
+    supervisor: Supervisor,
+    // Processes the service started that may still run, for `running.launched`.
+    launched: HashSet<u32>,
+    // When each toggle or stop rule last started or stopped its program.
+    toggled: HashMap<String, Instant>,
+    // Launches waiting for somebody to log on.
+    queue: Queue,
+    // Launches whose readiness probes are still running.
+    verifying: Vec<Pending>,
+}
+
+impl Context {
+    fn new(config: Config, config_path: Option<String>, installed: (Action, String), dry_run: bool,
+           state: SharedState, metrics: Arc<Metrics>) -> Result<Context, String> {
+        let host_ip = config.host_ip(&installed.1);
+        let interface = select_interface(&host_ip)
+            .ok_or_else(|| format!("Could not find the interface with IP address starting with: {}", host_ip))?;
+        info!(interface = interface.name.as_str(); "Found an interface with target IP address starting with: {}", host_ip);
+        let default_action = config.default_action(&installed.0);
+        let rules = config.effective_rules();
+        #[cfg(windows)]
+        if rules.iter().any(|rule| rule.limits.is_some()) {
+            warn!("Rule limits are only applied on Linux, they are ignored");
+        }
+        let authenticator = config.auth.as_ref().map(Authenticator::new);
+        {
+            let mut state = state.lock().unwrap();
+            state.rules = rules.clone();
+            state.interfaces = vec![interface.clone()];
         }
+        let history = if config.history.enabled {
+            match History::open(&config.history) {
+                Ok(history) => Some(Mutex::new(history)),
+                Err(e) => {
+                    error!("Wake history disabled: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Context { config, config_path, installed, dry_run, default_action, interface, reopen_capture: false,
+                     rules, authenticator, state, metrics, history, supervisor: Supervisor::default(),
+                     launched: HashSet::new(), toggled: HashMap::new(), queue: Queue::default(), verifying: Vec::new() })
+    }
+
+    // The --dry-run flag holds across reloads; the config can turn dry runs on as well.
+    fn dry_run(&self) -> bool {
+        self.dry_run || self.config.service.dry_run
     }
-}
 
-// Generate the windows service boilerplate.
-// The boilerplate contains the low-level service entry function (ffi_service_main) that parses
-// incoming service arguments into Vec<OsString> and passes them to user defined service
-// entry (wol_service_main).
-define_windows_service!(ffi_service_main, wol_service_main);
-
-pub fn wol_service_main(_: Vec<OsString>) {
-    let arguments: Vec<String> = env::args().collect();
-    // Create a channel to be able to poll a stop event from the service worker loop.
-    let (shutdown_tx, shutdown_rx) = mpsc::channel();
-    let shutdown_rx = Arc::new(Mutex::new(shutdown_rx));
-
-    // Define system service event handler that will be receiving service events.
-    let event_handler = move |control_event| -> ServiceControlHandlerResult {
-        match control_event {
-            // Notifies a service to report its current status information to the service
-            // control manager. Always return NoError even if not implemented.
-            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
-
-            // Handle stop
-            ServiceControl::Stop => {
-                shutdown_tx.send(()).unwrap();
-                ServiceControlHandlerResult::NoError
+    // Builds a complete new context before swapping it in, so an invalid config leaves the old one in effect.
+    fn reload(&mut self) -> Result<String, String> {
+        let path = self.config_path.clone()
+            .ok_or_else(|| String::from("The service was installed without a config file"))?;
+        let config = Config::load(&path)?;
+        let auth_unchanged = config.auth == self.config.auth;
+        let restart_needed = config.api != self.config.api || config.metrics != self.config.metrics;
+        // Capturing on another interface has to work before the old channel is given up.
+        let interface = select_interface(&config.host_ip(&self.installed.1));
+        if let Some(interface) = interface.filter(|interface| interface.name != self.interface.name) {
+            open_capture(&interface).map_err(|e| format!("{}; still capturing on {}", e, self.interface.name))?;
+        }
+        let mut context = Context::new(config, Some(path.clone()), self.installed.clone(), self.dry_run,
+                                       Arc::clone(&self.state), Arc::clone(&self.metrics))?;
+        if restart_needed {
+            warn!("API or metrics settings changed; they take effect after a restart");
+        }
+        // Keep the nonces seen so far unless the key changed, so a reload does not open a replay window.
+        if auth_unchanged {
+            context.authenticator = self.authenticator.take();
+        }
+        context.supervisor = std::mem::take(&mut self.supervisor);
+        context.launched = std::mem::take(&mut self.launched);
+        // A reload within the repeat window must not let the repeated packets of the last wake undo it.
+        context.toggled = std::mem::take(&mut self.toggled);
+        context.queue = std::mem::take(&mut self.queue);
+        context.verifying = std::mem::take(&mut self.verifying);
+        context.supervisor.update(&context.rules, &context.default_action);
+        context.reopen_capture = context.interface.name != self.interface.name;
+        if context.reopen_capture {
+            info!(interface = context.interface.name.as_str(); "Capture moves to interface {}", context.interface.name);
+        }
+        *self = context;
+        info!("Reloaded config file {}", path);
+        Ok(format!("Reloaded {}", path))
+    }
+
+    fn record(&self, record: WakeRecord) {
+        if let Some(history) = &self.history {
+            if let Err(e) = history.lock().unwrap().append(&record) {
+                warn!("{}", e);
             }
+        }
+        self.state.lock().unwrap().record(record);
+    }
 
-            _ => ServiceControlHandlerResult::NotImplemented,
+    // Records a wake event that did not start anything.
+    fn reject(&self, record: WakeRecord, reason: RejectReason) {
+        self.metrics.rejected(reason);
+        self.record(record);
+    }
+
+    // Remembers a program just started for `rule` and hands it to the supervisor if the rule asks for it.
+    // The launch is recorded right away, or once the rule's readiness probes decided how it went.
+    fn started(&mut self, rule: &str, action: Action, process: Process, origin: WakeRecord) {
+        let pid = process.id();
+        self.launched.insert(pid);
+        let found = self.rules.iter().find(|candidate| candidate.name == rule);
+        let (supervise, ready) = (found.and_then(|rule| rule.supervise.clone()), found.and_then(|rule| rule.ready.clone()));
+        if let Some(ready) = ready {
+            info!(rule, program = action.run_path.as_str(); "Waiting up to {}s for {} to be ready: {}",
+                  ready.timeout_secs, action.run_path, ready.describe());
+            self.verifying.push(Pending::start(rule, action.clone(), pid, origin.clone(), &ready));
+        } else {
+            self.record(origin.clone());
         }
-    };
+        if let Some(supervise) = supervise {
+            self.supervisor.watch(rule, action, supervise, process, origin);
+        }
+    }
 
-    // Register system service event handler.
-    // The returned status handle should be used to report service status changes to the system.
-    let status_handle: Option<ServiceStatusHandle> =
-        match service_control_handler::register(SERVICE_NAME, event_handler) {
-            Ok(handle) => Some(handle),
-            Err(e) => {
-                error!("Failed to register the service control handler: {:?}", e);
-                None
+    // Records the launches whose readiness probes finished, stopping programs that did not get
+    // ready if their rule asks for it.
+    fn verify(&mut self) {
+        if self.verifying.is_empty() {
+            return;
+        }
+        let mut finished = Vec::new();
+        for pending in std::mem::take(&mut self.verifying) {
+            match pending.result() {
+                Some(result) => finished.push((pending, result)),
+                None => self.verifying.push(pending),
             }
-        };
+        }
+        for (pending, result) in finished {
+            let rule = pending.rule.as_str();
+            let record = match result {
+                Ok(ready) => {
+                    info!(rule, program = pending.action.run_path.as_str(); "{} is {}", pending.action.run_path, ready);
+                    pending.report(Outcome::Launched, ready)
+                },
+                Err(reason) => {
+                    warn!(rule, program = pending.action.run_path.as_str(); "{} (process {}) {}",
+                          pending.action.run_path, pending.pid, reason);
+                    // Only what this launch started: another copy of the program may have been running all along.
+                    let pids = stop::tree(pending.pid);
+                    let detail = if pending.on_failure == OnFailure::Stop && !pids.is_empty() {
+                        let (_, stopped) = self.stop(rule, &pending.action, pids);
+                        format!("{}; {}", reason, stopped)
+                    } else {
+                        reason
+                    };
+                    pending.report(Outcome::NotReady, detail)
+                },
+            };
+            self.record(record);
+        }
+    }
 
-    // Tell the system that service is running
-    set_status(&status_handle, "Service status set to RUNNING", ServiceState::Running, 0);
+    // Restarts crashed programs and records what the supervisor saw.
+    fn supervise(&mut self) {
+        let metrics = Arc::clone(&self.metrics);
+        let mut restarted = Vec::new();
+        let dry_run = self.dry_run();
+        let records = self.supervisor.poll(&self.launched, dry_run, |rule, action| {
+            let result = launch(action, rule);
+            metrics.launch(result.is_ok());
+            if let Ok(process) = &result {
+                restarted.push(process.id());
+            }
+            result
+        });
+        self.launched.extend(restarted);
+        for record in records {
+            self.record(record);
+        }
+    }
 
-    debug!("Service entry function called with arguments: {:?}", arguments);
+    // Queues the launch while nobody, or not the action's user, is logged on, if the queue is enabled.
+    // None means start it now.
+    fn defer(&mut self, rule: &str, action: &Action, origin: WakeRecord) -> Option<QueuedLaunch> {
+        if !self.config.queue.enabled || session::find(action.user.as_deref()).is_some() {
+            return None;
+        }
+        let (launch, replaced) = self.queue.push(rule, action.clone(), origin, &self.config.queue);
+        if let Some(entry) = replaced {
+            info!(rule, program = action.run_path.as_str(); "A later wake replaces the queued launch of {}", entry.action.run_path);
+            self.record(entry.report(Outcome::Cancelled, String::from("replaced by a later wake"), None));
+        }
+        info!(rule, program = action.run_path.as_str(); "{}; {} waits for a user session until {}",
+              not_logged_on(action), action.run_path, launch.expires_at.format("%Y-%m-%d %H:%M:%S"));
+        self.state.lock().unwrap().queue = self.queue.launches();
+        Some(launch)
+    }
 
-    // Ensure we have at least 4 arguments.
-    if arguments.len() < 4 {
-        set_status(&status_handle, "Insufficient arguments provided to the service", ServiceState::Stopped, 1);
-        return;
+    // Drops queued launches that expired and starts the others once somebody is logged on.
+    fn run_queue(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+        for entry in self.queue.expire() {
+            warn!(rule = entry.launch.rule.as_str(), program = entry.action.run_path.as_str();
+                  "{} logged on before the queued launch of {} expired",
+                  entry.action.user.as_deref().map_or(String::from("Nobody"), |user| format!("{} never", user)),
+                  entry.action.run_path);
+            let expired = format!("no user session since {}", entry.launch.queued_at.format("%Y-%m-%d %H:%M:%S"));
+            self.record(entry.report(Outcome::Expired, expired, None));
+        }
+        let ready = self.queue.take(|entry| session::find(entry.action.user.as_deref()));
+        for (entry, session) in ready {
+            let rule = entry.launch.rule.as_str();
+            let queued = format!("queued at {}", entry.launch.queued_at.format("%Y-%m-%d %H:%M:%S"));
+            if is_program_running(&entry.action, Some(&self.launched)) {
+                info!(rule, program = entry.action.run_path.as_str(); "{} is already running, dropping its queued launch",
+                      entry.action.program);
+                self.reject(entry.report(Outcome::AlreadyRunning, queued, None), RejectReason::AlreadyRunning);
+                continue;
+            }
+            // Queued before a reload turned dry runs on.
+            if self.dry_run() {
+                let plan = launch_plan(&entry.action);
+                info!(rule, program = entry.action.run_path.as_str(); "{} logged on, dry run, would start the queued {}",
+                      session.user, plan);
+                self.record(entry.report(Outcome::DryRun, format!("{}; would start {}", queued, plan), None));
+                continue;
+            }
+            info!(rule, program = entry.action.run_path.as_str(); "{} logged on, starting the queued {}",
+                  session.user, entry.action.run_path);
+            let result = launch(&entry.action, rule);
+            self.metrics.launch(result.is_ok());
+            match result {
+                Ok(process) => {
+                    let detail = format!("{}, started once {} logged on", queued, session.user);
+                    let record = entry.report(Outcome::Launched, detail, Some(process.id()));
+                    self.started(rule, entry.action.clone(), process, record);
+                },
+                Err(e) => {
+                    self.record(entry.report(Outcome::Failed, format!("{}; error {}", queued, e), None));
+                },
+            }
+        }
+        self.state.lock().unwrap().queue = self.queue.launches();
     }
 
-    // Skipping arguments[0] as it's the service name.
-    let prg = arguments[1].as_str();
-    let run_path = arguments[2].as_str();
-    let host_ip = arguments[3].as_str();
+    // Asks the processes of `action` to exit for a toggle or stop rule, returning the outcome to record
+    // and what was done.
+    fn stop(&mut self, rule: &str, action: &Action, pids: Vec<u32>) -> (Outcome, String) {
+        let grace = self.rules.iter().find(|candidate| candidate.name == rule)
+            .map_or_else(|| Rule::default().stop_grace(), Rule::stop_grace);
+        let processes = pids.iter().map(u32::to_string).collect::<Vec<_>>().join(", ");
+        if self.dry_run() {
+            info!(rule, program = action.run_path.as_str(); "Dry run, would stop {} (process {})", action.program, processes);
+            return (Outcome::DryRun, format!("would stop process {}, killing it after {}s", processes, grace.as_secs()));
+        }
+        self.supervisor.release(action);
+        info!(rule, program = action.run_path.as_str(); "Stopping {} (process {}), killing it after {}s",
+              action.program, processes, grace.as_secs());
+        stop::stop(&action.program, pids, grace);
+        (Outcome::Stopped, format!("asked process {} to exit, killing it after {}s", processes, grace.as_secs()))
+    }
 
-    if let Err(e) = run_service(prg, run_path, host_ip,
-        shutdown_rx,
-        status_handle
-    ) {
-       set_status(&status_handle, "Error running the service: {e}", ServiceState::Stopped, 1);
+    // Drops the processes that ended from `launched`, so a reused ID is not taken for one of ours.
+    fn forget_exited(&mut self) {
+        if self.launched.is_empty() {
+            return;
+        }
+        let mut system = System::new();
+        system.refresh_processes();
+        self.launched.retain(|pid| system.process(Pid::from_u32(*pid)).is_some());
     }
 }
 
-pub fn run_service(prg: &str, run_path: &str, host_ip: &str,
+// `installed` holds the program, run path and host ip the service was installed with.
+pub fn run_service(installed: (Action, String), dry_run: bool, config: Config, config_path: Option<String>,
                    shutdown_rx:Arc<Mutex<Receiver<()>>>,
-                   status_handle: Option<ServiceStatusHandle>) -> Result<()> {
+                   (control_tx, control_rx): (Sender<Control>, Receiver<Control>),
+                   status_handle: Option<StatusHandle>) -> Result<(), String> {
         let sleep_duration = std::time::Duration::from_secs(1);
         let mut sleep_counter = 0;
         let inner_shutdown_rx = Arc::clone(&shutdown_rx);
-        let interfaces = datalink::interfaces();
-        let interface = match interfaces.into_iter().find(|iface| {
-            iface.ips.iter().any(|ip| ip.to_string().starts_with(host_ip))
-        }) {
-            Some(found_interface) => {
-                info!("Found an interface with target IP address starting with: {}", host_ip);
-                found_interface
+        #[cfg(unix)]
+        forward_sighup(control_tx.clone());
+        let state: SharedState = Arc::new(Mutex::new(State::new(config_path.clone())));
+        let api = match &config.api {
+            Some(api_config) => match Api::start(api_config, Arc::clone(&state), control_tx) {
+                Ok(api) => Some(api),
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                }
             },
-            None => {
-                set_status(&status_handle, "Could not find the interface with IP address starting with: {host_ip}", ServiceState::Stopped, 1);
-                panic!("Could not find the interface with IP address starting with: {}", host_ip)
+            None => None,
+        };
+        let metrics = Arc::new(Metrics::default());
+        let metrics_server = match &config.metrics {
+            Some(metrics_config) => match MetricsServer::start(metrics_config, Arc::clone(&metrics)) {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let mut context = match Context::new(config, config_path, installed, dry_run, Arc::clone(&state), Arc::clone(&metrics)) {
+            Ok(context) => context,
+            Err(e) => {
+                report_stopped(&status_handle, &e, 1);
+                return Err(e);
             }
         };
 
-        loop {
+        let result = loop {
             if stop_signal_handler(&shutdown_rx) {
                 debug!("Received STOP signal in outer loop");
-                break;
+                break Ok(());
             }
-            if !is_program_running(prg) {
-                info!("{} not running; start listening for WOL packet", prg);
-                if listen_for_wol(&interface, run_path, inner_shutdown_rx.clone(), &status_handle) {
-                    break;
+            handle_controls(&control_rx, &mut context);
+            context.supervise();
+            context.verify();
+            context.run_queue();
+            context.forget_exited();
+            // Keep listening as long as any program the rules can start is not running.
+            let programs: Vec<ProgramStatus> = rules::all_actions(&context.rules, &context.default_action)
+                .into_iter()
+                .map(|action| ProgramStatus { running: is_program_running(&action, Some(&context.launched)),
+                                              program: action.program })
+                .collect();
+            for program in &programs {
+                metrics.program_running(&program.program, program.running);
+            }
+            let idle: Vec<&str> = programs.iter()
+                .filter(|program| !program.running)
+                .map(|program| program.program.as_str())
+                .collect();
+            // A toggle or stop rule has something to do while its program runs.
+            let stoppable = context.rules.iter().any(|rule| rule.mode != Mode::Start);
+            {
+                let mut state = state.lock().unwrap();
+                state.activity = if idle.is_empty() && !stoppable { Activity::Idle } else { Activity::Listening };
+                state.programs = programs.clone();
+            }
+            if !idle.is_empty() {
+                info!("{} not running; start listening for WOL packet", idle.join(", "));
+            } else if stoppable {
+                info!("All programs are running; listening for WOL packets to stop them");
+            }
+            if !idle.is_empty() || stoppable {
+                match listen_for_wol(&mut context, &control_rx, inner_shutdown_rx.clone()) {
+                    Ok(true) => break Ok(()),
+                    Ok(false) => {},
+                    Err(e) => break Err(e),
                 }
             }
 
@@ -137,119 +465,588 @@ pub fn run_service(prg: &str, run_path: &str, host_ip: &str,
             if sleep_counter >= 10 {
                 sleep_counter = 0;
             }
+        };
+        state.lock().unwrap().activity = Activity::Stopping;
+        if let Some(api) = api {
+            api.stop();
+        }
+        if let Some(metrics_server) = metrics_server {
+            metrics_server.stop();
         }
         // Tell the system that service has stopped.
-        set_status(&status_handle, "Service stopped...", ServiceState::Stopped, 0);
-        Ok(())
+        match &result {
+            Ok(()) => report_stopped(&status_handle, "Service stopped...", 0),
+            Err(e) => report_stopped(&status_handle, e, 1),
+        }
+        result
 }
 
-fn is_program_running(prg: &str) -> bool {
-    let sys = System::new_all();
-    let x = !sys.processes_by_name(prg).next().is_none();
-    x
+// The interface the service captures on: the first one with an address starting with `host_ip`.
+pub fn select_interface(host_ip: &str) -> Option<NetworkInterface> {
+    datalink::interfaces().into_iter().find(|iface| matches_host_ip(iface, host_ip))
 }
 
-fn is_wol_packet(packet: &[u8]) -> bool {
-    // Minimum length for WOL payload
-    if packet.len() < 6 + 16 * 6 {
-        return false;
-    }
-
-    let wol_start = packet.len() - (6 + 16 * 6);
-
-    // Check for 6 bytes of 0xFF
-    if packet[wol_start..wol_start + 6] != [0xff, 0xff, 0xff, 0xff, 0xff, 0xff] {
-        return false;
-    }
-
-    // Get the repeated MAC address from the packet
-    let mac = &packet[wol_start + 6..wol_start + 12];
-
-    // Check for 16 repetitions of the MAC address
-    for i in 0..16 {
-        if packet[wol_start + 6 + i * 6..wol_start + 6 + (i + 1) * 6] != *mac {
-            return false;
-        }
-    }
-
-    true
+pub fn matches_host_ip(interface: &NetworkInterface, host_ip: &str) -> bool {
+    interface.ips.iter().any(|ip| ip.to_string().starts_with(host_ip))
 }
 
-fn listen_for_wol(interface: &NetworkInterface, run_path: &str,
-                  shutdown_rx: Arc<Mutex<Receiver<()>>>, status_handle: &Option<ServiceStatusHandle>) -> bool{
-    let channel = datalink::channel(interface, Default::default()).unwrap();
+// `launched` holds the processes the service started, when known.
+pub fn is_program_running(action: &Action, launched: Option<&HashSet<u32>>) -> bool {
+    process_match::is_running(&action.program, action.running.as_ref(), launched)
+}
 
-    let mut rx = match channel {
-        datalink::Channel::Ethernet(_, rx) => {
+// Opens the capture channel, e.g. failing without the privileges to capture.
+fn open_capture(interface: &NetworkInterface) -> Result<Box<dyn datalink::DataLinkReceiver>, String> {
+    // Wake up every second even without traffic, so stop and control requests are handled promptly.
+    let channel_config = datalink::Config { read_timeout: Some(Duration::from_secs(1)), ..Default::default() };
+    match datalink::channel(interface, channel_config) {
+        Ok(datalink::Channel::Ethernet(_, rx)) => {
             debug!("Datalink channel created");
-            rx
+            Ok(rx)
         },
-        _ => {
-            set_status(&status_handle,"Failed to create datalink channel", ServiceState::Stopped, 1);
-            panic!("Failed to create datalink channel")
-        }
-    };
+        Ok(_) => Err(String::from("Failed to create datalink channel")),
+        Err(e) => Err(format!("Failed to capture on {}: {}", interface.name, e)),
+    }
+}
 
-    loop {
+// Ok(true) when the service is to stop, Ok(false) to return to the outer loop.
+fn listen_for_wol(context: &mut Context, control_rx: &Receiver<Control>,
+                  shutdown_rx: Arc<Mutex<Receiver<()>>>) -> Result<bool, String> {
+    let interface = context.interface.clone();
+    context.reopen_capture = false;
+    let mut rx = open_capture(&interface)?;
+
+    let (event, rule, action, detail) = loop {
         if stop_signal_handler(&shutdown_rx){
             debug!("Receive STOP signal in inner loop");
-            return true
+            return Ok(true)
+        }
+        handle_controls(control_rx, context);
+        context.supervise();
+        context.verify();
+        context.run_queue();
+        if context.reopen_capture {
+            debug!("Re-opening the capture channel");
+            return Ok(false)
         }
         match rx.next() {
             Ok(packet) => {
-                if is_wol_packet(packet) {
-                    info!("Wake-on-LAN packet detected!");
+                context.metrics.frame_inspected();
+                if let Some(event) = WakeEvent::from_frame(&interface.name, packet) {
+                    info!(interface = event.interface.as_str(), target_mac:% = event.target_mac, source = event.source();
+                          "Wake-on-LAN packet detected: {}", event);
+                    // Any sender picks the MAC, so only the ones rules name get a label of their own.
+                    let named = context.rules.iter().any(|rule| rule.target_mac == Some(event.target_mac));
+                    context.metrics.magic_packet(named.then_some(event.target_mac));
+                    if let Err((reason, detail)) = screen(&context.config, context.authenticator.as_mut(),
+                                                          &event, &interface.description) {
+                        warn!(interface = event.interface.as_str(), target_mac:% = event.target_mac, source = event.source();
+                              "Rejected Wake-on-LAN packet {}: {}", event, detail);
+                        context.reject(WakeRecord { detail: Some(detail), ..WakeRecord::new(&event, None, Outcome::Rejected) },
+                                       reason);
+                        continue;
+                    }
+                    let (rule, action, detail) = match rules::evaluate(&context.rules, &event, &context.default_action) {
+                        Decision::Run(rule, action) => {
+                            info!(target_mac:% = event.target_mac, source = event.source(), rule = rule.name.as_str();
+                                  "Rule {} matched {}", rule.name, event);
+                            (rule.name.clone(), action, None)
+                        },
+                        Decision::OutOfSchedule(rule, reason, fallback) => {
+                            warn!(target_mac:% = event.target_mac, source = event.source(), rule = rule.name.as_str();
+                                  "Wake-on-LAN packet {} outside the schedule of rule {}: {}", event, rule.name, reason);
+                            match fallback {
+                                Some(action) => (rule.name.clone(), action, Some(format!("fallback: {}", reason))),
+                                None => {
+                                    context.reject(WakeRecord { detail: Some(reason), ..WakeRecord::new(&event, Some(&rule.name), Outcome::OutOfSchedule) },
+                                                   RejectReason::OutOfSchedule);
+                                    continue;
+                                },
+                            }
+                        },
+                        Decision::NoMatch => {
+                            info!(target_mac:% = event.target_mac, source = event.source(); "No rule matches {}", event);
+                            context.reject(WakeRecord::new(&event, None, Outcome::NoMatch), RejectReason::NoMatch);
+                            continue;
+                        },
+                    };
+                    let mode = context.rules.iter().find(|candidate| candidate.name == rule).map_or(Mode::Start, |rule| rule.mode);
+                    if mode != Mode::Start {
+                        if context.toggled.get(&rule).is_some_and(|toggled| toggled.elapsed() < REPEAT_WINDOW) {
+                            info!(target_mac:% = event.target_mac, rule = rule.as_str(); "Ignoring a repeated wake for rule {}", rule);
+                            continue;
+                        }
+                        context.toggled.insert(rule.clone(), Instant::now());
+                    }
+                    let pids = process_match::find(&action.program, action.running.as_ref(), Some(&context.launched));
+                    match (mode, pids.is_empty()) {
+                        (Mode::Start, false) => {
+                            info!(target_mac:% = event.target_mac, rule = rule.as_str(); "{} is already running: found a process {}",
+                                  action.program, action.describe_running());
+                            context.reject(WakeRecord { action: Some(action.run_path),
+                                                        ..WakeRecord::new(&event, Some(&rule), Outcome::AlreadyRunning) },
+                                           RejectReason::AlreadyRunning);
+                            continue;
+                        },
+                        (Mode::Toggle | Mode::Stop, false) => {
+                            let (outcome, stopped) = context.stop(&rule, &action, pids);
+                            let detail = match detail {
+                                Some(detail) => format!("{}; {}", detail, stopped),
+                                None => stopped,
+                            };
+                            context.record(WakeRecord { action: Some(action.run_path), detail: Some(detail),
+                                                        ..WakeRecord::new(&event, Some(&rule), outcome) });
+                            continue;
+                        },
+                        (Mode::Stop, true) => {
+                            info!(target_mac:% = event.target_mac, rule = rule.as_str(); "{} is not running, nothing to stop",
+                                  action.program);
+                            context.reject(WakeRecord { action: Some(action.run_path),
+                                                        ..WakeRecord::new(&event, Some(&rule), Outcome::NotRunning) },
+                                           RejectReason::NotRunning);
+                            continue;
+                        },
+                        (Mode::Start | Mode::Toggle, true) => {},
+                    }
+                    if context.dry_run() {
+                        let plan = launch_plan(&action);
+                        info!(target_mac:% = event.target_mac, rule = rule.as_str(), program = action.run_path.as_str();
+                              "Dry run, would start {}", plan);
+                        let detail = match detail {
+                            Some(detail) => format!("{}; would start {}", detail, plan),
+                            None => format!("would start {}", plan),
+                        };
+                        context.record(WakeRecord { action: Some(action.run_path), detail: Some(detail),
+                                                    ..WakeRecord::new(&event, Some(&rule), Outcome::DryRun) });
+                        continue;
+                    }
+                    let origin = WakeRecord { action: Some(action.run_path.clone()), detail: detail.clone(),
+                                              ..WakeRecord::new(&event, Some(&rule), Outcome::Launched) };
+                    if let Some(launch) = context.defer(&rule, &action, origin.clone()) {
+                        let queued = format!("no session of {}, waiting until {}", action.user.as_deref().unwrap_or("any user"),
+                                             launch.expires_at.format("%Y-%m-%d %H:%M:%S"));
+                        let detail = match detail {
+                            Some(detail) => format!("{}; {}", detail, queued),
+                            None => queued,
+                        };
+                        context.record(WakeRecord { outcome: Outcome::Queued, detail: Some(detail), ..origin });
+                        continue;
+                    }
                     // Stop listening and break the loop.
-                    break;
+                    break (event, rule, action, detail);
                 }
             },
+            // A signal (SIGTERM, SIGHUP) interrupts the read; the loop picks it up next.
+            Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => continue,
             Err(e) => {
                 warn!("An error occurred while reading packet: {:?}", e);
+                context.metrics.capture_error();
                 continue;
             },
         }
+    };
+
+    let record = WakeRecord { action: Some(action.run_path.clone()), detail,
+                              ..WakeRecord::new(&event, Some(&rule), Outcome::Launched) };
+    let result = launch(&action, &rule);
+    context.metrics.launch(result.is_ok());
+    match result {
+        Ok(process) => {
+            let record = WakeRecord { pid: Some(process.id()), ..record };
+            context.started(&rule, action, process, record);
+        },
+        Err(e) => context.record(WakeRecord { outcome: Outcome::Failed, detail: Some(format!("error {}", e)), ..record }),
     }
+    Ok(false)
+}
 
-    match run_as_current_user::start_process_as_current_user(run_path,
-                                                             Some(""),
-                                                             run_path.split("\\").next(),
-                                                             true){
-        Ok(result) => {
-            info!("Successfully started the command with process id: {:?}", result);
-            false
+// The checks a wake event has to pass before the rules are consulted: source filters, then authentication.
+pub fn screen(config: &Config, authenticator: Option<&mut Authenticator>, event: &WakeEvent,
+              interface_description: &str) -> Result<(), (RejectReason, String)> {
+    if let Some(reason) = config.filter.check(event, interface_description) {
+        return Err((RejectReason::Filter, reason));
+    }
+    if let Some(authenticator) = authenticator {
+        authenticator.verify(event).map_err(|reason| (RejectReason::Auth, reason))?;
+    }
+    Ok(())
+}
+
+fn launch(action: &Action, rule: &str) -> Result<Process, i32> {
+    let run_path = action.run_path.as_str();
+    match start_process(action) {
+        Ok(process) => {
+            info!(rule, program = run_path, pid = process.id(); "Successfully started the command with process id: {:?}", process.id());
+            Ok(process)
         },
         Err(e) => {
-            error!("Failed to start {}. Error: {:?}", run_path, e);
-            false
+            error!(rule, program = run_path, error_code = e; "Failed to start {}. Error: {:?}", run_path, e);
+            Err(e)
         }
     }
 }
 
-fn set_status(status_handle: &Option<ServiceStatusHandle>, message: &str, state: ServiceState, exit: u32) {
-    if let Some(handle) = status_handle { // Use pattern matching here
-        let control_accepted = match state {
-            ServiceState::Stopped => ServiceControlAccept::empty(),
-            _ => ServiceControlAccept::STOP,
-        };
-        let status = ServiceStatus {
-            service_type: SERVICE_TYPE,
-            current_state: state,
-            controls_accepted: control_accepted,
-            exit_code: ServiceExitCode::Win32(exit),
-            checkpoint: 0,
-            wait_hint: Duration::default(),
-            process_id: None,
-        };
-        match handle.set_service_status(status) { // Use the handle here
-            Ok(_) => {
-                match exit {
-                    0 => { debug!("{}", message); },
-                    _ => { error!("{}", message); }
-                }
+// Why `action` cannot start yet, for messages.
+fn not_logged_on(action: &Action) -> String {
+    match &action.user {
+        Some(user) => format!("{} is not logged on", user),
+        None => String::from("Nobody is logged on"),
+    }
+}
+
+// How `start_process` would start `action`, for dry runs.
+#[cfg(windows)]
+fn launch_plan(action: &Action) -> String {
+    let user = match &action.user {
+        Some(user) => format!("{}, in their session", user),
+        None => String::from("the user logged on to the console"),
+    };
+    let plan = format!("{:?} with an empty command line in {:?}, in a new console of {}",
+                       action.run_path, action.run_path.split('\\').next().unwrap_or(""), user);
+    // The environment block is built from the user's token, so it is only known while they are logged on.
+    let environment = session::find(action.user.as_deref())
+        .and_then(|session| session.id.parse().ok())
+        .and_then(run_as_current_user::session_environment);
+    match environment {
+        Some(environment) => format!("{}, with environment {}", plan, describe_environment(environment)),
+        None => format!("{}, with their environment once they log on", plan),
+    }
+}
+
+#[cfg(unix)]
+fn launch_plan(action: &Action) -> String {
+    use std::env;
+    let working_dir = env::current_dir().map(|dir| dir.display().to_string()).unwrap_or_default();
+    let mut environment: Vec<(String, String)> = env::vars().collect();
+    let plan = match &action.user {
+        Some(user) => match (session::Account::lookup(user), session::find(Some(user))) {
+            (Some(account), Some(session)) => {
+                let plan = format!("{:?} without arguments as {} in {:?}", action.run_path, account.name, account.home);
+                environment.extend(user_environment(&account, &session));
+                plan
             },
-            Err(e) => {
-                error!("Failed to set service status. Error: {:?}", e);
+            (Some(account), None) => format!("{:?} without arguments as {} in {:?} once they log on, with their \
+                                              session's variables added", action.run_path, account.name, account.home),
+            (None, _) => format!("{:?} without arguments as the unknown user {}, which fails", action.run_path, user),
+        },
+        None => format!("{:?} without arguments in {:?}", action.run_path, working_dir),
+    };
+    let plan = format!("{}, with environment {}", plan, describe_environment(environment));
+    match action.limits.as_ref().filter(|limits| **limits != limits::Limits::default()) {
+        Some(limits) => format!("{}, limited to {}", plan, limits.describe()),
+        None => plan,
+    }
+}
+
+// The environment as KEY=value pairs sorted by name, a later value for a name replacing an earlier one.
+// Values of variables named like secrets are masked, as the plan ends up in the log and the history.
+fn describe_environment(environment: Vec<(String, String)>) -> String {
+    const SECRETS: [&str; 6] = ["PASSWORD", "PASSWD", "SECRET", "TOKEN", "KEY", "CREDENTIAL"];
+    let environment: std::collections::BTreeMap<String, String> = environment.into_iter().collect();
+    let variables: Vec<String> = environment.iter()
+        .map(|(key, value)| {
+            let secret = SECRETS.iter().any(|secret| key.to_ascii_uppercase().contains(secret));
+            format!("{}={}", key, if secret { "***" } else { value })
+        })
+        .collect();
+    variables.join(" ")
+}
+
+#[cfg(windows)]
+fn start_process(action: &Action) -> Result<Process, i32> {
+    let run_path = action.run_path.as_str();
+    let work_dir = run_path.split("\\").next();
+    let Some(user) = &action.user else {
+        return run_as_current_user::start_process_as_current_user(run_path, Some(""), work_dir, true).map(Process::open);
+    };
+    let Some(session) = session::find(Some(user)) else {
+        error!(program = run_path; "{} is not logged on, cannot start {} in their session", user, run_path);
+        return Err(-1);
+    };
+    // Session IDs on Windows are numbers.
+    let session_id = session.id.parse().map_err(|_| -1)?;
+    run_as_current_user::start_process_in_session(session_id, run_path, Some(""), work_dir, true).map(Process::open)
+}
+
+// Without a user the unit already runs as the user the programs belong to, so start them directly.
+// Cgroup limits need a transient scope, which systemd-run sets up before it execs the service again
+// to apply the rest of the limits in there, see `isolate`.
+#[cfg(unix)]
+fn start_process(action: &Action) -> Result<Process, i32> {
+    use std::{ffi::CString, os::unix::process::CommandExt, process::Command};
+    let run_path = action.run_path.as_str();
+    let limits = action.limits.clone().unwrap_or_default();
+    let target = match &action.user {
+        Some(user) => Some(user_session(user, run_path)?),
+        None => None,
+    };
+    // Switching needs root; for the service's own user there is nothing to switch.
+    let switch = target.as_ref().map(|(account, _)| account).filter(|account| account.uid != unsafe { libc::geteuid() });
+    let scope = limits.needs_cgroup() && limits::scope_available();
+    let executable = if scope {
+        let executable = std::env::current_exe().map_err(|e| {
+            error!(program = run_path; "Cannot locate the executable to start {} in a scope: {}", run_path, e);
+            -1
+        })?;
+        Some(executable)
+    } else {
+        None
+    };
+    let mut command = if let Some(executable) = &executable {
+        let mut command = Command::new(limits::SYSTEMD_RUN);
+        command.args(limits.scope_arguments(run_path))
+            .arg("--")
+            .arg(executable)
+            .arg(ISOLATE)
+            .arg(serde_json::to_string(&limits).map_err(|_| -1)?)
+            .arg(switch.map_or("", |account| account.name.as_str()))
+            .arg(run_path);
+        command
+    } else {
+        if limits.needs_cgroup() {
+            warn!(program = run_path; "systemd is not available, so {} gets no CPU or task limits and an address space \
+                  limit instead of a memory limit", run_path);
+        }
+        Command::new(run_path)
+    };
+    if let Some((account, session)) = &target {
+        debug!(program = run_path; "Starting {} as {} in session {}", run_path, account.name, session.id);
+        command.current_dir(&account.home).envs(user_environment(account, session));
+    }
+    let restrict = limits.restrict(scope);
+    // Command::uid would switch before pre_exec runs, leaving initgroups and the limits without
+    // the privileges they need. Inside a scope `isolate` switches instead.
+    let switch = match switch.filter(|_| !scope) {
+        Some(account) => Some((CString::new(account.name.clone()).map_err(|_| -1)?, account.uid, account.gid)),
+        None => None,
+    };
+    unsafe {
+        command.pre_exec(move || {
+            restrict()?;
+            if !scope {
+                become_user(switch.as_ref())?;
             }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().map_err(|e| e.raw_os_error().unwrap_or(-1))?;
+    if let Some(executable) = &executable {
+        wait_for_scope(&mut child, run_path, executable)?;
+    }
+    Ok(Process::new(child))
+}
+
+// Runs the service again inside a scope as `WolStart --isolate-in-scope <limits> <user> <program>`.
+#[cfg(unix)]
+pub const ISOLATE: &str = "--isolate-in-scope";
+
+// Inside the scope: gives the process the namespaces of the limits, no_new_privileges and the user,
+// if any, then becomes the program. Only returns if one of them fails.
+#[cfg(unix)]
+pub fn isolate(arguments: &[String]) -> String {
+    use std::{ffi::CString, os::unix::process::CommandExt, process::Command};
+    let [limits, user, run_path] = arguments else {
+        return format!("{} takes the limits, the user and the program", ISOLATE);
+    };
+    let limits: limits::Limits = match serde_json::from_str(limits) {
+        Ok(limits) => limits,
+        Err(e) => return format!("Invalid limits {}: {}", limits, e),
+    };
+    let switch = match user.as_str() {
+        "" => None,
+        user => match (session::Account::lookup(user), CString::new(user)) {
+            (Some(account), Ok(name)) => Some((name, account.uid, account.gid)),
+            _ => return format!("Unknown user {}, cannot start {}", user, run_path),
+        },
+    };
+    if let Err(e) = limits.isolate().and_then(|_| become_user(switch.as_ref())) {
+        return format!("Cannot apply the limits of {}: {}", run_path, e);
+    }
+    format!("Failed to start {}: {}", run_path, Command::new(run_path).exec())
+}
+
+// Switches to the groups, gid and uid of the account, then drops the capabilities a unit with --user
+// grants the service so the program does not get them. Only makes system calls, so it also runs
+// between fork and exec.
+#[cfg(unix)]
+fn become_user(switch: Option<&(std::ffi::CString, u32, u32)>) -> io::Result<()> {
+    if let Some((name, uid, gid)) = switch {
+        if unsafe { libc::initgroups(name.as_ptr(), *gid as _) != 0 || libc::setgid(*gid) != 0 || libc::setuid(*uid) != 0 } {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// How long systemd-run may take to set up the scope before the launch counts as started anyway.
+#[cfg(unix)]
+const SCOPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Waits until the process in the scope has become the program. systemd-run and `isolate` exit when
+// they fail, and that is reported instead of a launch; the exit code becomes the error code.
+#[cfg(unix)]
+fn wait_for_scope(child: &mut std::process::Child, run_path: &str, executable: &std::path::Path) -> Result<(), i32> {
+    let systemd_run = std::fs::canonicalize(limits::SYSTEMD_RUN).unwrap_or_else(|_| limits::SYSTEMD_RUN.into());
+    let exe = format!("/proc/{}/exe", child.id());
+    let deadline = Instant::now() + SCOPE_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                error!(program = run_path; "{} did not start in a scope, systemd-run exited with {}", run_path, status);
+                return Err(status.code().unwrap_or(-1));
+            },
+            Ok(None) => {},
+            Err(e) => return Err(e.raw_os_error().unwrap_or(-1)),
+        }
+        // After switching users the path cannot be read any more, so the program is about to start.
+        match std::fs::read_link(&exe) {
+            Ok(path) if path == systemd_run || path == executable => {},
+            _ => return Ok(()),
+        }
+        if Instant::now() >= deadline {
+            warn!(program = run_path; "systemd-run has not started {} after {}s", run_path, SCOPE_TIMEOUT.as_secs());
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// The account and one of the sessions of `user`, to start a program as them in that session: with their
+// IDs and groups, in their home directory and with the variables that lead to their display and session bus.
+#[cfg(unix)]
+fn user_session(user: &str, run_path: &str) -> Result<(session::Account, session::Session), i32> {
+    let Some(account) = session::Account::lookup(user) else {
+        error!(program = run_path; "Unknown user {}, cannot start {}", user, run_path);
+        return Err(-1);
+    };
+    let Some(session) = session::find(Some(user)) else {
+        error!(program = run_path; "{} is not logged on, cannot start {} in their session", account.name, run_path);
+        return Err(-1);
+    };
+    Ok((account, session))
+}
+
+// What a program started as `account` in `session` gets on top of the service's environment.
+#[cfg(unix)]
+fn user_environment(account: &session::Account, session: &session::Session) -> Vec<(String, String)> {
+    let mut environment = vec![
+        (String::from("HOME"), account.home.clone()),
+        (String::from("USER"), account.name.clone()),
+        (String::from("LOGNAME"), account.name.clone()),
+    ];
+    environment.extend(session.environment.iter().cloned());
+    environment
+}
+
+// SIGHUP asks a daemon to reload its config, like the API's /reload.
+#[cfg(unix)]
+fn forward_sighup(control_tx: Sender<Control>) {
+    use std::sync::mpsc;
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!("Cannot handle SIGHUP, reload through the API instead: {}", e);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received SIGHUP, reloading the config");
+            // Nobody waits for the reply; the worker loop logs the result.
+            let (reply_tx, _) = mpsc::channel();
+            if control_tx.send(Control::Reload(reply_tx)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Answers all pending control requests without blocking.
+fn handle_controls(control_rx: &Receiver<Control>, context: &mut Context) {
+    while let Ok(control) = control_rx.try_recv() {
+        match control {
+            Control::Trigger(name, reply) => {
+                let found = context.rules.iter().find(|rule| rule.name == name)
+                    .map(|rule| (rule.action(&context.default_action), rule.mode, rule.target_mac));
+                let result = match found {
+                    Some((action, mode, target_mac)) => {
+                        let pids = process_match::find(&action.program, action.running.as_ref(), Some(&context.launched));
+                        if mode == Mode::Start && !pids.is_empty() {
+                            Err(format!("{} is already running", action.program))
+                        } else if mode != Mode::Start && !pids.is_empty() {
+                            let (outcome, stopped) = context.stop(&name, &action, pids);
+                            context.record(WakeRecord { detail: Some(stopped.clone()),
+                                                        ..WakeRecord::api(&name, target_mac, &action.run_path, outcome) });
+                            Ok(format!("Stopping {}: {}", action.program, stopped))
+                        } else if mode == Mode::Stop {
+                            Err(format!("{} is not running", action.program))
+                        } else if context.dry_run() {
+                            let plan = launch_plan(&action);
+                            let message = format!("Dry run, would start {}", plan);
+                            info!(rule = name.as_str(), program = action.run_path.as_str(); "{}", message);
+                            context.record(WakeRecord { detail: Some(format!("would start {}", plan)),
+                                                        ..WakeRecord::api(&name, target_mac, &action.run_path, Outcome::DryRun) });
+                            Ok(message)
+                        } else if let Some(launch) = context.defer(&name, &action, WakeRecord::api(&name, target_mac, &action.run_path,
+                                                                                                     Outcome::Launched)) {
+                            let queued = format!("no session of {}, waiting until {}", action.user.as_deref().unwrap_or("any user"),
+                                                 launch.expires_at.format("%Y-%m-%d %H:%M:%S"));
+                            context.record(WakeRecord { detail: Some(queued),
+                                                        ..WakeRecord::api(&name, target_mac, &action.run_path, Outcome::Queued) });
+                            Ok(format!("{}; {} is queued until {}", not_logged_on(&action), action.run_path,
+                                       launch.expires_at.format("%Y-%m-%d %H:%M:%S")))
+                        } else {
+                            let result = launch(&action, &name);
+                            context.metrics.launch(result.is_ok());
+                            match result {
+                                Ok(process) => {
+                                    let mut message = format!("Started {} with process id {}", action.run_path, process.id());
+                                    let rule = context.rules.iter().find(|rule| rule.name == name);
+                                    if let Some(ready) = rule.and_then(|rule| rule.ready.as_ref()) {
+                                        message.push_str(&format!(", waiting up to {}s for it to be ready", ready.timeout_secs));
+                                    }
+                                    let origin = WakeRecord { pid: Some(process.id()),
+                                                              ..WakeRecord::api(&name, target_mac, &action.run_path, Outcome::Launched) };
+                                    context.started(&name, action, process, origin);
+                                    Ok(message)
+                                },
+                                Err(e) => {
+                                    context.record(WakeRecord { detail: Some(format!("error {}", e)),
+                                                                ..WakeRecord::api(&name, target_mac, &action.run_path, Outcome::Failed) });
+                                    Err(format!("Failed to start {}. Error: {}", action.run_path, e))
+                                },
+                            }
+                        }
+                    },
+                    None => Err(format!("No rule named {}", name)),
+                };
+                let _ = reply.send(result);
+            },
+            Control::Reload(reply) => {
+                let result = context.reload();
+                if let Err(e) = &result {
+                    error!("Failed to reload config: {}", e);
+                }
+                let _ = reply.send(result);
+            },
+            Control::Cancel(rule, reply) => {
+                let cancelled = context.queue.cancel(rule.as_deref());
+                for entry in &cancelled {
+                    info!(rule = entry.launch.rule.as_str(), program = entry.action.run_path.as_str();
+                          "Cancelled the queued launch of {}", entry.action.run_path);
+                    context.record(entry.report(Outcome::Cancelled, String::from("cancelled through the API"), None));
+                }
+                context.state.lock().unwrap().queue = context.queue.launches();
+                let result = match (rule, cancelled.len()) {
+                    (Some(rule), 0) => Err(format!("No launch is queued for rule {}", rule)),
+                    (_, count) => Ok(format!("Cancelled {} queued launch{}", count, if count == 1 { "" } else { "es" })),
+                };
+                let _ = reply.send(result);
+            },
         }
     }
 }
@@ -0,0 +1,263 @@
+use std::{io::{Read, Write}, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+          sync::{Arc, mpsc::{self, Sender}}, thread::{self, JoinHandle}, time::Duration};
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use sha2::Sha256;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use super::control::Control;
+use super::state::SharedState;
+
+// How long a POST waits for the worker loop to act on it.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(30);
+// How long the CLI waits for the running service to answer.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8680))
+}
+
+// The `[api]` config section. The API is only started when the section is present.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+    #[serde(default = "default_address")]
+    pub address: SocketAddr,
+    // Expected as `Authorization: Bearer <token>`; mandatory unless bound to loopback.
+    pub token: Option<String>,
+}
+
+impl ApiConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.address.ip().is_loopback() && self.token.is_none() {
+            return Err(format!("api.token is required to bind the API to {}", self.address));
+        }
+        Ok(())
+    }
+}
+
+// The embedded HTTP server, accepting requests on its own thread until stopped. Each request is
+// handled on a thread of its own, so a POST waiting on the worker loop doesn't hold up the rest.
+pub struct Api {
+    server: Arc<Server>,
+    thread: JoinHandle<()>,
+}
+
+impl Api {
+    pub fn start(config: &ApiConfig, state: SharedState, control_tx: Sender<Control>) -> Result<Api, String> {
+        let server = Server::http(config.address)
+            .map_err(|e| format!("Failed to bind the API to {}: {}", config.address, e))?;
+        let server = Arc::new(server);
+        let token = config.token.clone();
+        let thread = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let token = token.clone();
+                    let state = Arc::clone(&state);
+                    let control_tx = control_tx.clone();
+                    thread::spawn(move || handle(request, token.as_deref(), &state, &control_tx));
+                }
+                debug!("API server stopped");
+            })
+        };
+        info!("API listening on http://{}", config.address);
+        Ok(Api { server, thread })
+    }
+
+    pub fn stop(self) {
+        self.server.unblock();
+        if self.thread.join().is_err() {
+            error!("API thread panicked");
+        }
+    }
+}
+
+// Sends a request to the running service's API and parses the JSON response, for CLI subcommands.
+pub fn request<T: DeserializeOwned>(config: &ApiConfig, method: &str, path: &str) -> Result<T, String> {
+    let mut address = config.address;
+    if address.ip().is_unspecified() {
+        address.set_ip(match address.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    let mut stream = TcpStream::connect_timeout(&address, CLIENT_TIMEOUT)
+        .map_err(|e| format!("Cannot connect to the API on {}: {}", address, e))?;
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT + CLIENT_TIMEOUT)).map_err(|e| e.to_string())?;
+
+    // HTTP/1.0, so the server closes the connection after the response.
+    let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: 0\r\n", method, path, address);
+    if let Some(token) = &config.token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).map_err(|e| format!("Failed to send API request: {}", e))?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|e| format!("Failed to read API response: {}", e))?;
+
+    let (status_line, body) = match response.split_once("\r\n\r\n") {
+        Some((head, body)) => (head.lines().next().unwrap_or(""), body),
+        None => return Err(String::from("Malformed API response")),
+    };
+    let status: u16 = status_line.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+        .ok_or_else(|| format!("Malformed API status line {}", status_line))?;
+    if status != 200 {
+        let message = serde_json::from_str::<serde_json::Value>(body).ok()
+            .and_then(|body| body.get("error").and_then(|error| error.as_str()).map(String::from))
+            .unwrap_or_else(|| status_line.to_string());
+        return Err(format!("API request {} {} failed: {}", method, path, message));
+    }
+    serde_json::from_str(body).map_err(|e| format!("Unexpected API response to {} {}: {}", method, path, e))
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+fn handle(request: Request, token: Option<&str>, state: &SharedState, control_tx: &Sender<Control>) {
+    debug!("API request {} {}", request.method(), request.url());
+    if let Some(token) = token {
+        let expected = format!("Bearer {}", token);
+        let authorized = request.headers().iter()
+            .any(|header| header.field.equiv("Authorization") && same_token(header.value.as_str(), &expected));
+        if !authorized {
+            warn!("Unauthorized API request {} {} from {:?}", request.method(), request.url(), request.remote_addr());
+            respond_error(request, 401, "unauthorized");
+            return;
+        }
+    }
+
+    let path: Vec<String> = request.url().split('?').next().unwrap_or("")
+        .split('/').filter(|segment| !segment.is_empty()).map(String::from).collect();
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    match (request.method(), path.as_slice()) {
+        (Method::Get, ["status"]) => {
+            let state = state.lock().unwrap();
+            let body = serde_json::json!({
+                "activity": state.activity,
+                "started_at": state.started_at,
+                "config_path": state.config_path,
+                "programs": state.programs,
+                "queue": state.queue,
+                "last_event": state.recent_events.back(),
+            });
+            drop(state);
+            respond_json(request, 200, &body);
+        },
+        (Method::Get, ["interfaces"]) => {
+            let interfaces = state.lock().unwrap().interfaces.clone();
+            respond_json(request, 200, &interfaces);
+        },
+        (Method::Get, ["rules"]) => {
+            let rules = state.lock().unwrap().rules.clone();
+            respond_json(request, 200, &rules);
+        },
+        (Method::Get, ["events"]) => {
+            let events = state.lock().unwrap().recent_events.clone();
+            respond_json(request, 200, &events);
+        },
+        (Method::Get, ["queue"]) => {
+            let queue = state.lock().unwrap().queue.clone();
+            respond_json(request, 200, &queue);
+        },
+        (Method::Delete, ["queue"]) => {
+            info!("API request to cancel all queued launches");
+            send_control(request, control_tx, |reply| Control::Cancel(None, reply));
+        },
+        (Method::Delete, ["queue", rule]) => {
+            let rule = rule.to_string();
+            info!("API request to cancel the queued launch of rule {}", rule);
+            send_control(request, control_tx, |reply| Control::Cancel(Some(rule), reply));
+        },
+        (Method::Post, ["rules", name, "trigger"]) => {
+            let name = name.to_string();
+            info!("API request to trigger rule {}", name);
+            send_control(request, control_tx, |reply| Control::Trigger(name, reply));
+        },
+        (Method::Post, ["reload"]) => {
+            info!("API request to reload the config");
+            send_control(request, control_tx, Control::Reload);
+        },
+        (_, ["status"]) | (_, ["interfaces"]) | (_, ["rules"]) | (_, ["events"])
+        | (_, ["rules", _, "trigger"]) | (_, ["reload"]) | (_, ["queue"]) | (_, ["queue", _]) => respond_error(request, 405, "method not allowed"),
+        _ => respond_error(request, 404, "not found"),
+    }
+}
+
+// Compares the HMACs of both values rather than the values themselves: verify_slice takes the same
+// time wherever they differ, and the tags have the same length whatever the length of the token.
+fn same_token(provided: &str, expected: &str) -> bool {
+    let tag = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"WolStart API token").expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        mac
+    };
+    tag(provided).verify_slice(&tag(expected).finalize().into_bytes()).is_ok()
+}
+
+fn send_control<F>(request: Request, control_tx: &Sender<Control>, control: F)
+    where F: FnOnce(mpsc::Sender<Result<String, String>>) -> Control {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    if control_tx.send(control(reply_tx)).is_err() {
+        respond_error(request, 503, "service is stopping");
+        return;
+    }
+    match reply_rx.recv_timeout(CONTROL_TIMEOUT) {
+        Ok(Ok(message)) => respond_json(request, 200, &serde_json::json!({ "result": message })),
+        Ok(Err(e)) => respond_error(request, 422, &e),
+        Err(_) => respond_error(request, 504, "service did not respond in time"),
+    }
+}
+
+fn respond_json<T: Serialize + ?Sized>(request: Request, status: u16, body: &T) {
+    let body = match serde_json::to_string_pretty(body) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to serialize API response: {}", e);
+            return respond_error(request, 500, "internal error");
+        }
+    };
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap());
+    if let Err(e) = request.respond(response) {
+        debug!("Failed to send API response: {}", e);
+    }
+}
+
+fn respond_error(request: Request, status: u16, message: &str) {
+    respond_json(request, status, &ErrorBody { error: message })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+    use crate::service::state::State;
+
+    #[test]
+    fn answers_while_a_control_waits() {
+        let (control_tx, control_rx) = mpsc::channel();
+        let config = ApiConfig { address: SocketAddr::from(([127, 0, 0, 1], 0)), token: None };
+        let api = Api::start(&config, Arc::new(Mutex::new(State::new(None))), control_tx).unwrap();
+        let config = ApiConfig { address: api.server.server_addr().to_ip().unwrap(), ..config };
+
+        let trigger = {
+            let config = config.clone();
+            thread::spawn(move || request::<serde_json::Value>(&config, "POST", "/rules/start/trigger"))
+        };
+        // The trigger now waits for the worker loop to reply.
+        let reply = match control_rx.recv().unwrap() {
+            Control::Trigger(name, reply) if name == "start" => reply,
+            _ => panic!("expected a trigger of rule start"),
+        };
+        let status: serde_json::Value = request(&config, "GET", "/status").unwrap();
+        assert!(status.get("activity").is_some());
+
+        reply.send(Ok(String::from("started"))).unwrap();
+        assert_eq!(trigger.join().unwrap().unwrap(), serde_json::json!({ "result": "started" }));
+        api.stop();
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+use hmac::{Hmac, Mac};
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use super::wake_event::{magic_packet, WakeEvent, MAGIC_PACKET_LEN};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TIMESTAMP_LEN: usize = 8;
+const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+// Trailer appended to the magic packet: timestamp (big endian unix seconds), nonce, HMAC tag.
+pub const TRAILER_LEN: usize = TIMESTAMP_LEN + NONCE_LEN + TAG_LEN;
+
+fn default_max_skew_secs() -> u64 {
+    30
+}
+
+// The `[auth]` config section. When present, only authenticated wake packets are accepted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    // Shared secret, also passed to `send --key`.
+    pub key: String,
+    // How far the sender's clock may be off, in seconds.
+    #[serde(default = "default_max_skew_secs")]
+    pub max_skew_secs: u64,
+}
+
+// Verifies authentication trailers and remembers nonces seen within the skew window,
+// so a captured packet cannot be replayed.
+pub struct Authenticator {
+    key: Vec<u8>,
+    max_skew_secs: u64,
+    seen_nonces: HashMap<[u8; NONCE_LEN], u64>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Authenticator {
+        Authenticator {
+            key: config.key.as_bytes().to_vec(),
+            max_skew_secs: config.max_skew_secs,
+            seen_nonces: HashMap::new(),
+        }
+    }
+
+    pub fn verify(&mut self, event: &WakeEvent) -> Result<(), String> {
+        self.verify_at(event, unix_time())
+    }
+
+    fn verify_at(&mut self, event: &WakeEvent, now: u64) -> Result<(), String> {
+        if event.trailer.len() < TRAILER_LEN {
+            return Err(String::from("packet is not authenticated"));
+        }
+        let (signed, tag) = event.trailer[..TRAILER_LEN].split_at(TIMESTAMP_LEN + NONCE_LEN);
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(&magic_packet(event.target_mac));
+        mac.update(signed);
+        if mac.verify_slice(tag).is_err() {
+            return Err(String::from("invalid authentication tag"));
+        }
+
+        let mut timestamp = [0u8; TIMESTAMP_LEN];
+        timestamp.copy_from_slice(&signed[..TIMESTAMP_LEN]);
+        let timestamp = u64::from_be_bytes(timestamp);
+        if now.abs_diff(timestamp) > self.max_skew_secs {
+            return Err(format!("timestamp is {} seconds off", now.abs_diff(timestamp)));
+        }
+
+        // Nonces older than the skew window are rejected by the timestamp check anyway.
+        let max_skew_secs = self.max_skew_secs;
+        self.seen_nonces.retain(|_, seen| now.abs_diff(*seen) <= max_skew_secs);
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&signed[TIMESTAMP_LEN..]);
+        if self.seen_nonces.insert(nonce, timestamp).is_some() {
+            return Err(String::from("replayed nonce"));
+        }
+        Ok(())
+    }
+}
+
+// Builds an authenticated wake packet. The magic packet comes first, so NIC firmware
+// still recognizes it.
+pub fn signed_magic_packet(key: &[u8], target_mac: MacAddr) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).expect("Failed to generate nonce");
+    sign(key, target_mac, unix_time(), nonce)
+}
+
+fn sign(key: &[u8], target_mac: MacAddr, timestamp: u64, nonce: [u8; NONCE_LEN]) -> Vec<u8> {
+    let mut packet = magic_packet(target_mac);
+    packet.extend_from_slice(&timestamp.to_be_bytes());
+    packet.extend_from_slice(&nonce);
+
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&packet);
+    packet.extend_from_slice(&mac.finalize().into_bytes());
+    debug_assert_eq!(packet.len(), MAGIC_PACKET_LEN + TRAILER_LEN);
+    packet
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use super::super::wake_event::Encapsulation;
+
+    const KEY: &str = "correct horse battery staple";
+    const NOW: u64 = 1_800_000_000;
+    const TARGET: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(&AuthConfig { key: KEY.to_string(), max_skew_secs: 30 })
+    }
+
+    // The event the service would see for `packet`, a magic packet with its trailer.
+    fn event(packet: &[u8]) -> WakeEvent {
+        WakeEvent {
+            received_at: Local::now(),
+            interface: String::from("test"),
+            target_mac: MacAddr::new(packet[6], packet[7], packet[8], packet[9], packet[10], packet[11]),
+            source_mac: MacAddr::zero(),
+            source_ip: None,
+            source_port: None,
+            encapsulation: Encapsulation::Udp(9),
+            trailer: packet[MAGIC_PACKET_LEN..].to_vec(),
+        }
+    }
+
+    fn signed(timestamp: u64, nonce: u8) -> Vec<u8> {
+        sign(KEY.as_bytes(), TARGET, timestamp, [nonce; NONCE_LEN])
+    }
+
+    #[test]
+    fn accepts_valid_tag() {
+        assert_eq!(authenticator().verify_at(&event(&signed(NOW, 1)), NOW), Ok(()));
+    }
+
+    #[test]
+    fn accepts_the_packet_send_builds() {
+        let packet = signed_magic_packet(KEY.as_bytes(), TARGET);
+        assert_eq!(authenticator().verify(&event(&packet)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let packet = sign(b"another key", TARGET, NOW, [1; NONCE_LEN]);
+        assert_eq!(authenticator().verify_at(&event(&packet), NOW), Err(String::from("invalid authentication tag")));
+    }
+
+    #[test]
+    fn rejects_tampered_tag() {
+        let mut packet = signed(NOW, 1);
+        *packet.last_mut().unwrap() ^= 1;
+        assert_eq!(authenticator().verify_at(&event(&packet), NOW), Err(String::from("invalid authentication tag")));
+    }
+
+    #[test]
+    fn rejects_tampered_mac() {
+        let mut packet = signed(NOW, 1);
+        let other = magic_packet(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x66));
+        packet[..MAGIC_PACKET_LEN].copy_from_slice(&other);
+        assert_eq!(authenticator().verify_at(&event(&packet), NOW), Err(String::from("invalid authentication tag")));
+    }
+
+    #[test]
+    fn rejects_tampered_timestamp() {
+        let mut packet = signed(NOW, 1);
+        packet[MAGIC_PACKET_LEN..MAGIC_PACKET_LEN + TIMESTAMP_LEN].copy_from_slice(&(NOW + 1).to_be_bytes());
+        assert_eq!(authenticator().verify_at(&event(&packet), NOW), Err(String::from("invalid authentication tag")));
+    }
+
+    #[test]
+    fn accepts_skew_at_the_bound() {
+        let mut authenticator = authenticator();
+        assert_eq!(authenticator.verify_at(&event(&signed(NOW - 30, 1)), NOW), Ok(()));
+        assert_eq!(authenticator.verify_at(&event(&signed(NOW + 30, 2)), NOW), Ok(()));
+    }
+
+    #[test]
+    fn rejects_skew_past_the_bound() {
+        let mut authenticator = authenticator();
+        assert_eq!(authenticator.verify_at(&event(&signed(NOW - 31, 1)), NOW),
+                   Err(String::from("timestamp is 31 seconds off")));
+        assert_eq!(authenticator.verify_at(&event(&signed(NOW + 31, 2)), NOW),
+                   Err(String::from("timestamp is 31 seconds off")));
+    }
+
+    #[test]
+    fn rejects_replayed_nonce() {
+        let mut authenticator = authenticator();
+        let packet = signed(NOW, 1);
+        assert_eq!(authenticator.verify_at(&event(&packet), NOW), Ok(()));
+        assert_eq!(authenticator.verify_at(&event(&packet), NOW + 1), Err(String::from("replayed nonce")));
+    }
+
+    #[test]
+    fn evicts_nonces_older_than_the_skew_window() {
+        let mut authenticator = authenticator();
+        assert_eq!(authenticator.verify_at(&event(&signed(NOW, 1)), NOW), Ok(()));
+        assert_eq!(authenticator.verify_at(&event(&signed(NOW + 31, 2)), NOW + 31), Ok(()));
+        assert_eq!(authenticator.seen_nonces.len(), 1);
+        assert!(authenticator.seen_nonces.contains_key(&[2; NONCE_LEN]));
+        // Past the window the timestamp check still rejects the first packet.
+        assert!(authenticator.verify_at(&event(&signed(NOW, 1)), NOW + 31).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_trailer() {
+        let packet = signed(NOW, 1);
+        for length in [MAGIC_PACKET_LEN, MAGIC_PACKET_LEN + 6, packet.len() - 1] {
+            assert_eq!(authenticator().verify_at(&event(&packet[..length]), NOW),
+                       Err(String::from("packet is not authenticated")));
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use std::sync::mpsc::Sender;
+
+// Reply to a control request: a short confirmation or an error message.
+pub type Reply = Sender<Result<String, String>>;
+
+// Requests sent to the worker loop from outside, e.g. by the control API.
+pub enum Control {
+    // Start the named rule's program as if a wake packet had arrived.
+    Trigger(String, Reply),
+    // Re-read the config file.
+    Reload(Reply),
+    // Drop the queued launch of the named rule, or all queued launches.
+    Cancel(Option<String>, Reply),
+}
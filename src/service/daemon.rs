@@ -0,0 +1,27 @@
+use std::{env, process};
+use log::{debug, error, info};
+use super::foreground;
+
+// Without a Windows-style service manager there is no handle to report status through;
+// systemd follows the process itself and takes the exit status and the log.
+pub enum StatusHandle {}
+
+// Runs as a plain process under systemd, stopping on SIGTERM or SIGINT.
+pub fn run() -> Result<(), String> {
+    info!("Starting the service...");
+    let arguments: Vec<String> = env::args().collect();
+    debug!("Service started with arguments: {:?}", arguments);
+    // Failures have been logged already; the exit status tells systemd.
+    if foreground::run(arguments.get(1..).unwrap_or_default(), false).is_err() {
+        process::exit(1);
+    }
+    info!("Service stopped successfully.");
+    Ok(())
+}
+
+pub fn report_stopped(_: &Option<StatusHandle>, message: &str, exit: u32) {
+    match exit {
+        0 => debug!("{}", message),
+        _ => error!("{}", message),
+    }
+}
@@ -0,0 +1,176 @@
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use super::wake_event::WakeEvent;
+
+// Source rules from the `[filter]` config section.
+// A wake event is rejected if it matches any deny rule. If allow rules are present,
+// it is also rejected unless it matches at least one of them.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub allow: Vec<SourceRule>,
+    pub deny: Vec<SourceRule>,
+}
+
+// Every field that is set must match; unset fields match anything.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceRule {
+    pub mac: Option<MacAddr>,
+    // Single address or CIDR block, e.g. "192.168.1.0/24".
+    pub ip: Option<IpNetwork>,
+    pub port: Option<u16>,
+    // Matched against the adapter name or its description.
+    pub interface: Option<String>,
+}
+
+impl SourceRule {
+    pub fn matches(&self, event: &WakeEvent, interface_description: &str) -> bool {
+        if let Some(mac) = self.mac {
+            if mac != event.source_mac {
+                return false;
+            }
+        }
+        if let Some(network) = self.ip {
+            match event.source_ip {
+                Some(ip) if network.contains(ip) => {},
+                _ => return false,
+            }
+        }
+        if let Some(port) = self.port {
+            if event.source_port != Some(port) {
+                return false;
+            }
+        }
+        if let Some(interface) = &self.interface {
+            if !interface.eq_ignore_ascii_case(&event.interface)
+                && !interface.eq_ignore_ascii_case(interface_description) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl FilterConfig {
+    // Returns the reason for rejecting the event, or None if it is accepted.
+    pub fn check(&self, event: &WakeEvent, interface_description: &str) -> Option<String> {
+        if let Some(index) = self.deny.iter().position(|rule| rule.matches(event, interface_description)) {
+            return Some(format!("matched deny rule #{}", index + 1));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|rule| rule.matches(event, interface_description)) {
+            return Some(String::from("no allow rule matched"));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use chrono::Local;
+    use super::super::wake_event::Encapsulation;
+
+    const SENDER: MacAddr = MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb);
+
+    fn filter(toml: &str) -> FilterConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn event(ip: &str, port: u16) -> WakeEvent {
+        WakeEvent {
+            received_at: Local::now(),
+            interface: String::from("eth0"),
+            target_mac: MacAddr::zero(),
+            source_mac: SENDER,
+            source_ip: Some(ip.parse::<IpAddr>().unwrap()),
+            source_port: Some(port),
+            encapsulation: Encapsulation::Udp(9),
+            trailer: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_everything_without_rules() {
+        assert_eq!(filter("").check(&event("10.0.0.1", 9), ""), None);
+    }
+
+    #[test]
+    fn an_empty_allow_list_allows_what_is_not_denied() {
+        let filter = filter(r#"deny = [{ ip = "10.0.0.0/8" }]"#);
+        assert_eq!(filter.check(&event("192.168.1.20", 9), ""), None);
+        assert_eq!(filter.check(&event("10.1.2.3", 9), "").as_deref(), Some("matched deny rule #1"));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = filter(r#"
+            allow = [{ ip = "192.168.1.0/24" }]
+            deny = [{ port = 7 }, { ip = "192.168.1.20" }]
+        "#);
+        assert_eq!(filter.check(&event("192.168.1.21", 9), ""), None);
+        assert_eq!(filter.check(&event("192.168.1.20", 9), "").as_deref(), Some("matched deny rule #2"));
+        assert_eq!(filter.check(&event("192.168.1.21", 7), "").as_deref(), Some("matched deny rule #1"));
+        assert_eq!(filter.check(&event("192.168.2.1", 9), "").as_deref(), Some("no allow rule matched"));
+    }
+
+    #[test]
+    fn matches_ipv4_cidr_boundaries() {
+        let filter = filter(r#"allow = [{ ip = "192.168.1.64/26" }]"#);
+        assert_eq!(filter.check(&event("192.168.1.64", 9), ""), None);
+        assert_eq!(filter.check(&event("192.168.1.127", 9), ""), None);
+        assert!(filter.check(&event("192.168.1.63", 9), "").is_some());
+        assert!(filter.check(&event("192.168.1.128", 9), "").is_some());
+        // An IPv4 block never matches an IPv6 sender.
+        assert!(filter.check(&event("::ffff:192.168.1.64", 9), "").is_some());
+    }
+
+    #[test]
+    fn matches_ipv6_cidr_boundaries() {
+        let filter = filter(r#"allow = [{ ip = "fd00:1::/64" }]"#);
+        assert_eq!(filter.check(&event("fd00:1::", 9), ""), None);
+        assert_eq!(filter.check(&event("fd00:1::ffff:ffff:ffff:ffff", 9), ""), None);
+        assert!(filter.check(&event("fd00:0:ffff:ffff:ffff:ffff:ffff:ffff", 9), "").is_some());
+        assert!(filter.check(&event("fd00:2::", 9), "").is_some());
+        assert!(filter.check(&event("192.168.1.1", 9), "").is_some());
+    }
+
+    #[test]
+    fn a_rule_with_an_ip_does_not_match_a_sender_without_one() {
+        let mut event = event("192.168.1.20", 9);
+        event.source_ip = None;
+        event.source_port = None;
+        assert!(filter(r#"deny = [{ ip = "0.0.0.0/0" }]"#).check(&event, "").is_none());
+        assert!(filter(r#"allow = [{ ip = "0.0.0.0/0" }]"#).check(&event, "").is_some());
+    }
+
+    #[test]
+    fn matches_ports() {
+        let filter = filter("allow = [{ port = 9 }]");
+        assert_eq!(filter.check(&event("10.0.0.1", 9), ""), None);
+        assert!(filter.check(&event("10.0.0.1", 7), "").is_some());
+    }
+
+    #[test]
+    fn matches_interface_names_and_descriptions_ignoring_case() {
+        let filter = filter(r#"allow = [{ interface = "ETH0" }, { interface = "Intel(R) Ethernet" }]"#);
+        assert_eq!(filter.check(&event("10.0.0.1", 9), ""), None);
+        let mut other = event("10.0.0.1", 9);
+        other.interface = String::from("wlan0");
+        assert!(filter.check(&other, "Wireless").is_some());
+        assert_eq!(filter.check(&other, "intel(r) ethernet"), None);
+    }
+
+    #[test]
+    fn every_field_of_a_rule_has_to_match() {
+        let filter = filter(&format!(r#"deny = [{{ mac = "{}", ip = "10.0.0.1", port = 9, interface = "eth0" }}]"#, SENDER));
+        assert!(filter.check(&event("10.0.0.1", 9), "").is_some());
+        assert_eq!(filter.check(&event("10.0.0.1", 7), ""), None);
+        assert_eq!(filter.check(&event("10.0.0.2", 9), ""), None);
+        let mut other = event("10.0.0.1", 9);
+        other.source_mac = MacAddr::zero();
+        assert_eq!(filter.check(&other, ""), None);
+    }
+}
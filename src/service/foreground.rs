@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex, mpsc::{self, Sender}};
+use log::{error, info};
+use super::run_from_arguments;
+
+// Runs the service pipeline without a service manager until interrupted: Ctrl-C, or SIGTERM
+// on unix. `arguments` are the launch arguments: program, run path, host ip, log level and
+// the optional config file. With `dry_run` nothing is started, only recorded.
+// Failures are logged before they are returned.
+pub fn run(arguments: &[String], dry_run: bool) -> Result<(), String> {
+    let (shutdown_tx, shutdown_rx) = mpsc::channel();
+    let shutdown_rx = Arc::new(Mutex::new(shutdown_rx));
+    let (control_tx, control_rx) = mpsc::channel();
+    if let Err(e) = on_interrupt(shutdown_tx) {
+        error!("{}", e);
+        return Err(e);
+    }
+    run_from_arguments(arguments, dry_run, shutdown_rx, (control_tx, control_rx), None)
+}
+
+#[cfg(unix)]
+fn on_interrupt(shutdown_tx: Sender<()>) -> Result<(), String> {
+    use signal_hook::{consts::{SIGINT, SIGTERM}, iterator::Signals};
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .map_err(|e| format!("Failed to handle termination signals: {}", e))?;
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!("Received signal {}, stopping", signal);
+            let _ = shutdown_tx.send(());
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn on_interrupt(shutdown_tx: Sender<()>) -> Result<(), String> {
+    use std::{io, sync::OnceLock};
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::{consoleapi::SetConsoleCtrlHandler, wincon::{CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT}};
+
+    // The handler runs on a thread of its own, with nothing but this to reach the worker loop.
+    static SHUTDOWN_TX: OnceLock<Mutex<Sender<()>>> = OnceLock::new();
+    unsafe extern "system" fn handler(event: DWORD) -> BOOL {
+        match event {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT => {
+                info!("Received Ctrl-C, stopping");
+                if let Some(shutdown_tx) = SHUTDOWN_TX.get() {
+                    let _ = shutdown_tx.lock().unwrap().send(());
+                }
+                TRUE
+            },
+            _ => FALSE,
+        }
+    }
+
+    SHUTDOWN_TX.set(Mutex::new(shutdown_tx))
+        .map_err(|_| String::from("The Ctrl-C handler is already installed"))?;
+    if unsafe { SetConsoleCtrlHandler(Some(handler), TRUE) } == 0 {
+        return Err(format!("Failed to handle Ctrl-C: {}", io::Error::last_os_error()));
+    }
+    Ok(())
+}
@@ -0,0 +1,319 @@
+use std::{env, fs::{self, File, OpenOptions}, io::{BufRead, BufReader, Write}, path::{Path, PathBuf},
+          str::FromStr, time::{Duration, Instant}};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use log::{info, warn};
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use super::wake_event::{Outcome, WakeRecord};
+
+// Prune at most this often even if the entry limit is not reached, to enforce the age limit.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// The `[history]` config section: a JSON Lines file with one `WakeRecord` per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    // Defaults to wol_history.jsonl next to the executable.
+    pub path: Option<PathBuf>,
+    // 0 disables the limit.
+    pub max_entries: usize,
+    pub max_age_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig { enabled: true, path: None, max_entries: 10000, max_age_days: 90 }
+    }
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> PathBuf {
+        match &self.path {
+            Some(path) => path.clone(),
+            // The systemd unit gives the service /var/lib/wolstart to write to.
+            None if cfg!(unix) => PathBuf::from("/var/lib/wolstart/wol_history.jsonl"),
+            None => env::current_exe()
+                .map(|exe| exe.with_file_name("wol_history.jsonl"))
+                .unwrap_or_else(|_| PathBuf::from("wol_history.jsonl")),
+        }
+    }
+}
+
+// Appends records to the history file and applies the retention limits.
+pub struct History {
+    config: HistoryConfig,
+    path: PathBuf,
+    entries: usize,
+    last_pruned: Instant,
+}
+
+impl History {
+    pub fn open(config: &HistoryConfig) -> Result<History, String> {
+        let path = config.path();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create history directory {}: {}", dir.display(), e))?;
+        }
+        let mut history = History { config: config.clone(), path, entries: 0, last_pruned: Instant::now() };
+        history.prune()?;
+        info!("Recording wake history in {}", history.path.display());
+        Ok(history)
+    }
+
+    pub fn append(&mut self, record: &WakeRecord) -> Result<(), String> {
+        let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .map_err(|e| format!("Failed to open history file {}: {}", self.path.display(), e))?;
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("Failed to write history file {}: {}", self.path.display(), e))?;
+        self.entries += 1;
+
+        // Allow 10% slack so the file is not rewritten on every append once full.
+        let max_entries = self.config.max_entries;
+        if (max_entries > 0 && self.entries > max_entries + max_entries / 10)
+            || self.last_pruned.elapsed() >= PRUNE_INTERVAL {
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    // Drops records beyond the retention limits, rewriting the file only if something was dropped.
+    fn prune(&mut self) -> Result<(), String> {
+        self.last_pruned = Instant::now();
+        if !self.path.exists() {
+            self.entries = 0;
+            return Ok(());
+        }
+        let mut records = read(&self.path)?;
+        let total = records.len();
+        if self.config.max_age_days > 0 {
+            let cutoff = Local::now() - chrono::Duration::days(self.config.max_age_days as i64);
+            records.retain(|record| record.time >= cutoff);
+        }
+        if self.config.max_entries > 0 && records.len() > self.config.max_entries {
+            records.drain(..records.len() - self.config.max_entries);
+        }
+        self.entries = records.len();
+        if records.len() == total {
+            return Ok(());
+        }
+
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut file = File::create(&tmp_path)
+            .map_err(|e| format!("Failed to create {}: {}", tmp_path.display(), e))?;
+        for record in &records {
+            let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+            writeln!(file, "{}", line).map_err(|e| format!("Failed to write {}: {}", tmp_path.display(), e))?;
+        }
+        drop(file);
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace history file {}: {}", self.path.display(), e))?;
+        info!("Pruned {} old entries from the wake history", total - records.len());
+        Ok(())
+    }
+}
+
+// Reads every record of a history file, skipping lines that cannot be parsed.
+pub fn read(path: &Path) -> Result<Vec<WakeRecord>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open history file {}: {}", path.display(), e))?;
+    let mut records = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read history file {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => warn!("Skipping line {} of {}: {}", index + 1, path.display(), e),
+        }
+    }
+    Ok(records)
+}
+
+// Parses `YYYY-MM-DD`, `YYYY-MM-DD HH:MM` (both local time) or an RFC 3339 timestamp.
+pub fn parse_time(s: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Local));
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| format!("invalid time {}, expected YYYY-MM-DD, YYYY-MM-DD HH:MM or RFC 3339", s))?;
+    Local.from_local_datetime(&naive).earliest()
+        .ok_or_else(|| format!("{} does not exist in the local time zone", s))
+}
+
+// Matches the sender either by MAC or by IP address / CIDR block.
+#[derive(Debug, Clone, Copy)]
+pub enum Source {
+    Mac(MacAddr),
+    Ip(IpNetwork),
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Source, String> {
+        if let Ok(mac) = s.parse::<MacAddr>() {
+            return Ok(Source::Mac(mac));
+        }
+        s.parse::<IpNetwork>()
+            .map(Source::Ip)
+            .map_err(|_| format!("{} is neither a MAC address nor an IP address or CIDR block", s))
+    }
+}
+
+// Filters for the `history` subcommand; unset fields match everything.
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+    pub target_mac: Option<MacAddr>,
+    pub source: Option<Source>,
+    pub outcome: Option<Outcome>,
+}
+
+impl HistoryQuery {
+    pub fn matches(&self, record: &WakeRecord) -> bool {
+        self.since.is_none_or(|since| record.time >= since)
+            && self.until.is_none_or(|until| record.time < until)
+            && self.target_mac.is_none_or(|mac| record.target_mac == mac)
+            && self.outcome.is_none_or(|outcome| record.outcome == outcome)
+            && self.source.is_none_or(|source| match source {
+                Source::Mac(mac) => record.source_mac == mac,
+                Source::Ip(network) => record.source_ip.is_some_and(|ip| network.contains(ip)),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    const TARGET: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+    const SENDER: MacAddr = MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb);
+
+    fn record(time: &str, outcome: Outcome) -> WakeRecord {
+        WakeRecord {
+            time: parse_time(time).unwrap(),
+            source_mac: SENDER,
+            source_ip: Some(IpAddr::from([192, 168, 1, 20])),
+            ..WakeRecord::api("rule", Some(TARGET), "/usr/bin/kodi", outcome)
+        }
+    }
+
+    // A history file of its own for each test, removed again when it ends.
+    struct TempHistory(PathBuf);
+
+    impl TempHistory {
+        fn new(name: &str, records: &[WakeRecord]) -> TempHistory {
+            let path = env::temp_dir().join(format!("wolstart-test-{}-{}.jsonl", std::process::id(), name));
+            let lines: Vec<String> = records.iter().map(|record| serde_json::to_string(record).unwrap()).collect();
+            fs::write(&path, lines.iter().map(|line| format!("{}\n", line)).collect::<String>()).unwrap();
+            TempHistory(path)
+        }
+
+        fn open(&self, max_entries: usize, max_age_days: u64) -> History {
+            History::open(&HistoryConfig { enabled: true, path: Some(self.0.clone()), max_entries, max_age_days }).unwrap()
+        }
+
+        fn len(&self) -> usize {
+            read(&self.0).unwrap().len()
+        }
+    }
+
+    impl Drop for TempHistory {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn parses_times() {
+        let midnight = Local.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap();
+        assert_eq!(parse_time("2026-10-19"), Ok(midnight));
+        assert_eq!(parse_time("2026-10-19 07:30"), Ok(Local.with_ymd_and_hms(2026, 10, 19, 7, 30, 0).unwrap()));
+        let utc = DateTime::parse_from_rfc3339("2026-10-19T05:30:00Z").unwrap();
+        assert_eq!(parse_time("2026-10-19T05:30:00Z"), Ok(utc.with_timezone(&Local)));
+        assert_eq!(parse_time("2026-10-19T07:30:00+02:00"), Ok(utc.with_timezone(&Local)));
+        for invalid in ["", "yesterday", "2026-13-01", "2026-10-19 25:00", "19.10.2026"] {
+            assert!(parse_time(invalid).is_err(), "{} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_sources() {
+        assert!(matches!("66:77:88:99:aa:bb".parse(), Ok(Source::Mac(SENDER))));
+        assert!(matches!("192.168.1.0/24".parse::<Source>(), Ok(Source::Ip(_))));
+        assert!(matches!("fd00::1".parse::<Source>(), Ok(Source::Ip(_))));
+        assert!("kodi".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn queries_match_every_field_that_is_set() {
+        let record = record("2026-10-19 07:30", Outcome::Launched);
+        assert!(HistoryQuery::default().matches(&record));
+        let since = |time| HistoryQuery { since: Some(parse_time(time).unwrap()), ..HistoryQuery::default() };
+        let until = |time| HistoryQuery { until: Some(parse_time(time).unwrap()), ..HistoryQuery::default() };
+        // `since` includes its time, `until` does not.
+        assert!(since("2026-10-19 07:30").matches(&record));
+        assert!(!since("2026-10-19 07:31").matches(&record));
+        assert!(until("2026-10-19 07:31").matches(&record));
+        assert!(!until("2026-10-19 07:30").matches(&record));
+
+        let target = |mac| HistoryQuery { target_mac: Some(mac), ..HistoryQuery::default() };
+        assert!(target(TARGET).matches(&record));
+        assert!(!target(SENDER).matches(&record));
+        let outcome = |outcome| HistoryQuery { outcome: Some(outcome), ..HistoryQuery::default() };
+        assert!(outcome(Outcome::Launched).matches(&record));
+        assert!(!outcome(Outcome::Failed).matches(&record));
+        let source = |source: &str| HistoryQuery { source: Some(source.parse().unwrap()), ..HistoryQuery::default() };
+        assert!(source("66:77:88:99:aa:bb").matches(&record));
+        assert!(!source("00:00:00:00:00:01").matches(&record));
+        assert!(source("192.168.1.0/24").matches(&record));
+        assert!(!source("192.168.2.0/24").matches(&record));
+        let api = WakeRecord { source_ip: None, ..record.clone() };
+        assert!(!source("0.0.0.0/0").matches(&api));
+
+        let query = HistoryQuery { target_mac: Some(TARGET), outcome: Some(Outcome::Failed), ..HistoryQuery::default() };
+        assert!(!query.matches(&record));
+    }
+
+    #[test]
+    fn prunes_entries_beyond_the_slack() {
+        let history = TempHistory::new("slack", &[]);
+        let mut file = history.open(10, 0);
+        let now = Local::now().format("%Y-%m-%d %H:%M").to_string();
+        // Up to 10% more than max_entries are kept until the next append.
+        for _ in 0..11 {
+            file.append(&record(&now, Outcome::Launched)).unwrap();
+        }
+        assert_eq!(history.len(), 11);
+        file.append(&record(&now, Outcome::Failed)).unwrap();
+        let records = read(&history.0).unwrap();
+        assert_eq!(records.len(), 10);
+        // The oldest records go.
+        assert_eq!(records.last().unwrap().outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn prunes_old_entries_on_open() {
+        let recent = Local::now().format("%Y-%m-%d %H:%M").to_string();
+        let history = TempHistory::new("age", &[record("2020-01-01", Outcome::Launched), record(&recent, Outcome::Failed)]);
+        history.open(0, 90);
+        let records = read(&history.0).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn applies_only_the_limits_that_are_set() {
+        let history = TempHistory::new("unlimited", &vec![record("2020-01-01", Outcome::Launched); 3]);
+        history.open(0, 0);
+        assert_eq!(history.len(), 3);
+        history.open(2, 0);
+        assert_eq!(history.len(), 2);
+    }
+}
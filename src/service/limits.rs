@@ -0,0 +1,227 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+// Wraps programs with cgroup limits in a transient scope unit.
+#[cfg(unix)]
+pub const SYSTEMD_RUN: &str = "/usr/bin/systemd-run";
+
+// The `limits` table of a rule: constraints for the started program and everything it starts.
+// Only applied on Linux.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // Scheduling priority from -20 (most favourable) to 19.
+    pub nice: Option<i32>,
+    // CPU time in percent of one CPU; above 100 for more than one.
+    pub cpu_percent: Option<u32>,
+    pub memory_mb: Option<u64>,
+    // Processes and threads together.
+    pub max_tasks: Option<u64>,
+    pub open_files: Option<u64>,
+    // CPU seconds after which the program is killed.
+    pub cpu_time_secs: Option<u64>,
+    // Neither the program nor its children can gain privileges, e.g. through setuid executables.
+    pub no_new_privileges: bool,
+    pub namespaces: Vec<Namespace>,
+}
+
+// A namespace the program gets of its own instead of sharing the service's.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Namespace {
+    // Only a loopback interface, which is down: no network access.
+    Network,
+    Ipc,
+    Uts,
+    Mount,
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Namespace::Network => write!(f, "network"),
+            Namespace::Ipc => write!(f, "ipc"),
+            Namespace::Uts => write!(f, "uts"),
+            Namespace::Mount => write!(f, "mount"),
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(nice) = self.nice.filter(|nice| !(-20..=19).contains(nice)) {
+            return Err(format!("nice {} is not between -20 and 19", nice));
+        }
+        if self.cpu_percent == Some(0) || self.memory_mb == Some(0) || self.max_tasks == Some(0) {
+            return Err(String::from("cpu_percent, memory_mb and max_tasks must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+// Whether the cgroup limits can go through a transient scope: systemd runs the machine
+// and systemd-run is installed.
+#[cfg(unix)]
+pub fn scope_available() -> bool {
+    std::path::Path::new("/run/systemd/system").is_dir() && std::path::Path::new(SYSTEMD_RUN).is_file()
+}
+
+#[cfg(unix)]
+impl Limits {
+    // The limits only a cgroup can enforce.
+    pub fn needs_cgroup(&self) -> bool {
+        self.cpu_percent.is_some() || self.memory_mb.is_some() || self.max_tasks.is_some()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut limits = Vec::new();
+        limits.extend(self.nice.map(|nice| format!("nice {}", nice)));
+        limits.extend(self.cpu_percent.map(|cpu| format!("{}% CPU", cpu)));
+        limits.extend(self.memory_mb.map(|memory| format!("{} MB of memory", memory)));
+        limits.extend(self.max_tasks.map(|tasks| format!("{} tasks", tasks)));
+        limits.extend(self.open_files.map(|files| format!("{} open files", files)));
+        limits.extend(self.cpu_time_secs.map(|secs| format!("{} CPU seconds", secs)));
+        if self.no_new_privileges {
+            limits.push(String::from("no new privileges"));
+        }
+        if !self.namespaces.is_empty() {
+            let namespaces: Vec<String> = self.namespaces.iter().map(Namespace::to_string).collect();
+            limits.push(format!("own {} namespaces", namespaces.join(", ")));
+        }
+        limits.join(", ")
+    }
+
+    // The systemd-run arguments before `--` and the command. systemd-run becomes the command, so the
+    // process ID stays the same.
+    pub fn scope_arguments(&self, program: &str) -> Vec<String> {
+        let mut arguments = vec![String::from("--scope"), String::from("--quiet"), String::from("--collect"),
+                                 format!("--description=WolStart: {}", program)];
+        arguments.extend(self.cpu_percent.map(|cpu| format!("--property=CPUQuota={}%", cpu)));
+        arguments.extend(self.memory_mb.map(|memory| format!("--property=MemoryMax={}M", memory)));
+        arguments.extend(self.max_tasks.map(|tasks| format!("--property=TasksMax={}", tasks)));
+        arguments
+    }
+
+    // Applies everything but the cgroup limits to the child process before it execs. Without a
+    // scope the memory limit becomes an address space limit instead, which counts reserved memory too.
+    // In a scope the namespaces and no_new_privileges would apply to systemd-run and keep it from
+    // reaching systemd, so they are left to `isolate` inside the scope.
+    // Runs between fork and exec, so it only makes system calls.
+    pub fn restrict(&self, scope: bool) -> impl Fn() -> std::io::Result<()> + Send + Sync + 'static {
+        use std::io::Error;
+        let nice = self.nice;
+        let address_space = self.memory_mb.filter(|_| !scope).map(|memory| memory.saturating_mul(1024 * 1024));
+        let open_files = self.open_files;
+        let cpu_time = self.cpu_time_secs;
+        let isolation = (!scope).then(|| self.clone());
+        move || {
+            if let Some(nice) = nice {
+                if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } != 0 {
+                    return Err(Error::last_os_error());
+                }
+            }
+            for (resource, limit) in [(libc::RLIMIT_AS, address_space), (libc::RLIMIT_NOFILE, open_files),
+                                      (libc::RLIMIT_CPU, cpu_time)] {
+                if let Some(limit) = limit {
+                    let limit = libc::rlimit { rlim_cur: limit as _, rlim_max: limit as _ };
+                    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                        return Err(Error::last_os_error());
+                    }
+                }
+            }
+            match &isolation {
+                Some(limits) => limits.isolate(),
+                None => Ok(()),
+            }
+        }
+    }
+
+    // Gives the process its own namespaces and keeps it from gaining privileges. Only makes system
+    // calls, so it also runs between fork and exec.
+    pub fn isolate(&self) -> std::io::Result<()> {
+        use std::io::Error;
+        let namespaces = self.namespaces.iter().fold(0, |flags, namespace| flags | match namespace {
+            Namespace::Network => libc::CLONE_NEWNET,
+            Namespace::Ipc => libc::CLONE_NEWIPC,
+            Namespace::Uts => libc::CLONE_NEWUTS,
+            Namespace::Mount => libc::CLONE_NEWNS,
+        });
+        if namespaces != 0 && unsafe { libc::unshare(namespaces) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        // systemd shares mounts both ways by default, which would defeat a mount namespace.
+        if namespaces & libc::CLONE_NEWNS != 0 {
+            let root = c"/";
+            let flags = libc::MS_REC | libc::MS_PRIVATE;
+            if unsafe { libc::mount(std::ptr::null(), root.as_ptr(), std::ptr::null(), flags, std::ptr::null()) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        if self.no_new_privileges && unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(toml: &str) -> Limits {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn accepts_nice_from_minus_20_to_19() {
+        for nice in [-20, 0, 19] {
+            assert_eq!(Limits { nice: Some(nice), ..Limits::default() }.validate(), Ok(()));
+        }
+        for nice in [-21, 20] {
+            assert_eq!(Limits { nice: Some(nice), ..Limits::default() }.validate(),
+                       Err(format!("nice {} is not between -20 and 19", nice)));
+        }
+    }
+
+    #[test]
+    fn rejects_zero_cgroup_limits() {
+        let error = Err(String::from("cpu_percent, memory_mb and max_tasks must be at least 1"));
+        assert_eq!(limits("cpu_percent = 0").validate(), error);
+        assert_eq!(limits("memory_mb = 0").validate(), error);
+        assert_eq!(limits("max_tasks = 0").validate(), error);
+        assert_eq!(limits("cpu_percent = 1\nmemory_mb = 1\nmax_tasks = 1").validate(), Ok(()));
+        // Zero open files or CPU seconds are strict, but valid.
+        assert_eq!(limits("open_files = 0\ncpu_time_secs = 0").validate(), Ok(()));
+    }
+
+    #[test]
+    fn reads_namespaces() {
+        let limits = limits(r#"namespaces = ["network", "ipc", "uts", "mount"]"#);
+        assert_eq!(limits.namespaces, [Namespace::Network, Namespace::Ipc, Namespace::Uts, Namespace::Mount]);
+        assert!(toml::from_str::<Limits>(r#"namespaces = ["pid"]"#).is_err());
+        assert!(toml::from_str::<Limits>("memory = 100").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn builds_the_systemd_run_arguments() {
+        let all = limits("cpu_percent = 150\nmemory_mb = 512\nmax_tasks = 64\nnice = 5\nopen_files = 100");
+        assert!(all.needs_cgroup());
+        assert_eq!(all.scope_arguments("/usr/bin/kodi"), [
+            "--scope", "--quiet", "--collect", "--description=WolStart: /usr/bin/kodi",
+            "--property=CPUQuota=150%", "--property=MemoryMax=512M", "--property=TasksMax=64",
+        ]);
+        assert_eq!(limits("memory_mb = 1").scope_arguments("kodi"), [
+            "--scope", "--quiet", "--collect", "--description=WolStart: kodi", "--property=MemoryMax=1M",
+        ]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn needs_a_cgroup_only_for_cgroup_limits() {
+        assert!(!limits("nice = 5\nopen_files = 100\ncpu_time_secs = 60\nno_new_privileges = true").needs_cgroup());
+        assert!(limits("max_tasks = 10").needs_cgroup());
+        assert_eq!(limits("nice = -5\nmemory_mb = 512\nno_new_privileges = true\nnamespaces = [\"network\"]").describe(),
+                   "nice -5, 512 MB of memory, no new privileges, own network namespaces");
+    }
+}
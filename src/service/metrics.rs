@@ -0,0 +1,179 @@
+use std::{collections::BTreeMap, fmt::Write, net::SocketAddr, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}},
+          thread::{self, JoinHandle}, time::Instant};
+use log::{debug, error, info};
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Response, Server};
+
+fn default_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9680))
+}
+
+// The `[metrics]` config section. Metrics are only served when the section is present.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    #[serde(default = "default_address")]
+    pub address: SocketAddr,
+}
+
+// Why a wake packet did not start anything, used as the `reason` label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RejectReason {
+    Filter,
+    Auth,
+    OutOfSchedule,
+    NoMatch,
+    AlreadyRunning,
+    NotRunning,
+}
+
+impl RejectReason {
+    fn label(self) -> &'static str {
+        match self {
+            RejectReason::Filter => "filter",
+            RejectReason::Auth => "auth",
+            RejectReason::OutOfSchedule => "out_of_schedule",
+            RejectReason::NoMatch => "no_match",
+            RejectReason::AlreadyRunning => "already_running",
+            RejectReason::NotRunning => "not_running",
+        }
+    }
+}
+
+// Counters and gauges updated by the worker loop and rendered in Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    frames_inspected: AtomicU64,
+    capture_errors: AtomicU64,
+    launches_attempted: AtomicU64,
+    launches_succeeded: AtomicU64,
+    launches_failed: AtomicU64,
+    magic_packets: Mutex<BTreeMap<String, u64>>,
+    rejected: Mutex<BTreeMap<RejectReason, u64>>,
+    programs_running: Mutex<BTreeMap<String, bool>>,
+    last_wake: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    pub fn frame_inspected(&self) {
+        self.frames_inspected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn capture_error(&self) {
+        self.capture_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Packets for a MAC no rule names count as `other`.
+    pub fn magic_packet(&self, target_mac: Option<MacAddr>) {
+        let target_mac = target_mac.map_or_else(|| String::from("other"), |mac| mac.to_string());
+        *self.magic_packets.lock().unwrap().entry(target_mac).or_insert(0) += 1;
+        *self.last_wake.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn rejected(&self, reason: RejectReason) {
+        *self.rejected.lock().unwrap().entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn launch(&self, succeeded: bool) {
+        self.launches_attempted.fetch_add(1, Ordering::Relaxed);
+        if succeeded {
+            self.launches_succeeded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.launches_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn program_running(&self, program: &str, running: bool) {
+        self.programs_running.lock().unwrap().insert(program.to_string(), running);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(&mut out, "wolstart_frames_inspected_total", "Ethernet frames inspected for magic packets.",
+                &[(String::new(), self.frames_inspected.load(Ordering::Relaxed))]);
+        counter(&mut out, "wolstart_capture_errors_total", "Errors returned while reading from the capture channel.",
+                &[(String::new(), self.capture_errors.load(Ordering::Relaxed))]);
+        counter(&mut out, "wolstart_magic_packets_total", "Magic packets detected, by target MAC named in a rule or other.",
+                &self.magic_packets.lock().unwrap().iter()
+                    .map(|(mac, count)| (label("target_mac", mac), *count))
+                    .collect::<Vec<_>>());
+        counter(&mut out, "wolstart_packets_rejected_total", "Magic packets that did not start a program, by reason.",
+                &self.rejected.lock().unwrap().iter()
+                    .map(|(reason, count)| (label("reason", reason.label()), *count))
+                    .collect::<Vec<_>>());
+        counter(&mut out, "wolstart_launches_attempted_total", "Program launches attempted.",
+                &[(String::new(), self.launches_attempted.load(Ordering::Relaxed))]);
+        counter(&mut out, "wolstart_launches_succeeded_total", "Program launches that succeeded.",
+                &[(String::new(), self.launches_succeeded.load(Ordering::Relaxed))]);
+        counter(&mut out, "wolstart_launches_failed_total", "Program launches that failed.",
+                &[(String::new(), self.launches_failed.load(Ordering::Relaxed))]);
+
+        let _ = writeln!(out, "# HELP wolstart_program_running Whether a program the rules can start is running.");
+        let _ = writeln!(out, "# TYPE wolstart_program_running gauge");
+        for (program, running) in self.programs_running.lock().unwrap().iter() {
+            let _ = writeln!(out, "wolstart_program_running{} {}", label("program", program), u8::from(*running));
+        }
+        // No sample at all until the first wake, rather than a misleading zero.
+        let _ = writeln!(out, "# HELP wolstart_seconds_since_last_wake Seconds since the last magic packet was detected.");
+        let _ = writeln!(out, "# TYPE wolstart_seconds_since_last_wake gauge");
+        if let Some(last_wake) = *self.last_wake.lock().unwrap() {
+            let _ = writeln!(out, "wolstart_seconds_since_last_wake {:.3}", last_wake.elapsed().as_secs_f64());
+        }
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(String, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+fn label(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("{{{}=\"{}\"}}", name, value)
+}
+
+// Serves `/metrics` for Prometheus on its own thread until stopped.
+pub struct MetricsServer {
+    server: Arc<Server>,
+    thread: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    pub fn start(config: &MetricsConfig, metrics: Arc<Metrics>) -> Result<MetricsServer, String> {
+        let server = Server::http(config.address)
+            .map_err(|e| format!("Failed to bind the metrics endpoint to {}: {}", config.address, e))?;
+        let server = Arc::new(server);
+        let thread = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let response = if request.url() == "/metrics" {
+                        Response::from_string(metrics.render())
+                            .with_header(Header::from_bytes(&b"Content-Type"[..],
+                                                            &b"text/plain; version=0.0.4"[..]).unwrap())
+                    } else {
+                        Response::from_string("not found\n").with_status_code(404)
+                    };
+                    if let Err(e) = request.respond(response) {
+                        debug!("Failed to send metrics response: {}", e);
+                    }
+                }
+                debug!("Metrics server stopped");
+            })
+        };
+        info!("Metrics available on http://{}/metrics", config.address);
+        Ok(MetricsServer { server, thread })
+    }
+
+    pub fn stop(self) {
+        self.server.unblock();
+        if self.thread.join().is_err() {
+            error!("Metrics thread panicked");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{collections::{HashMap, HashSet}, path::Path};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, PidExt, Process, ProcessExt, System, SystemExt, UserExt};
+
+// Linux keeps only this many characters of a process name.
+const LINUX_NAME_LEN: usize = 15;
+
+// The `running` table of a rule or fallback: how to tell that its program already runs.
+// Every key that is set has to match. Without `name`, `path` or `cmdline` a process named
+// exactly like `program` counts.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessMatch {
+    // The exact process name, ignoring case on Windows.
+    pub name: Option<String>,
+    // The exact path of the executable.
+    pub path: Option<String>,
+    // A regular expression searched for in the command line, its arguments joined by spaces.
+    pub cmdline: Option<String>,
+    // The user the process runs as.
+    pub user: Option<String>,
+    // Only processes the service started, or that those started and are still their children.
+    pub launched: bool,
+}
+
+impl ProcessMatch {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(cmdline) = &self.cmdline {
+            Regex::new(cmdline).map_err(|e| format!("invalid cmdline {}: {}", cmdline, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self, program: &str) -> String {
+        let mut criteria = Vec::new();
+        match (&self.name, &self.path, &self.cmdline) {
+            (None, None, None) => criteria.push(format!("named {}", program)),
+            (name, path, cmdline) => {
+                criteria.extend(name.as_ref().map(|name| format!("named {}", name)));
+                criteria.extend(path.as_ref().map(|path| format!("running {}", path)));
+                criteria.extend(cmdline.as_ref().map(|cmdline| format!("with a command line matching {}", cmdline)));
+            },
+        }
+        criteria.extend(self.user.as_ref().map(|user| format!("owned by {}", user)));
+        if self.launched {
+            criteria.push(String::from("started by the service"));
+        }
+        criteria.join(", ")
+    }
+}
+
+// Whether a process of `program` runs. `launched` holds the processes the service started;
+// outside the service it is unknown and `launched = true` is not checked.
+pub fn is_running(program: &str, process_match: Option<&ProcessMatch>, launched: Option<&HashSet<u32>>) -> bool {
+    !find(program, process_match, launched).is_empty()
+}
+
+// The IDs of the processes of `program`, see `is_running`.
+pub fn find(program: &str, process_match: Option<&ProcessMatch>, launched: Option<&HashSet<u32>>) -> Vec<u32> {
+    let default = ProcessMatch::default();
+    let process_match = process_match.unwrap_or(&default);
+    // Checked when the config is loaded.
+    let cmdline = process_match.cmdline.as_deref().and_then(|cmdline| Regex::new(cmdline).ok());
+    let mut system = System::new();
+    system.refresh_processes();
+    if process_match.user.is_some() {
+        system.refresh_users_list();
+    }
+    let processes = system.processes();
+    processes.values().filter(|process| {
+        let name = match (&process_match.name, &process_match.path, &cmdline) {
+            (None, None, None) => Some(program),
+            (name, _, _) => name.as_deref(),
+        };
+        name.is_none_or(|name| name_matches(process.name(), name))
+            && process_match.path.as_deref().is_none_or(|path| process.exe() == Path::new(path))
+            && cmdline.as_ref().is_none_or(|cmdline| cmdline.is_match(&process.cmd().join(" ")))
+            && process_match.user.as_deref().is_none_or(|user| {
+                process.user_id().and_then(|uid| system.get_user_by_id(uid)).is_some_and(|owner| owner.name() == user)
+            })
+            && (!process_match.launched || launched.is_none_or(|launched| was_launched(process, processes, launched)))
+    })
+    .map(|process| process.pid().as_u32())
+    .collect()
+}
+
+fn name_matches(actual: &str, expected: &str) -> bool {
+    if cfg!(windows) {
+        return actual.eq_ignore_ascii_case(expected);
+    }
+    actual == expected
+        || (actual.len() == LINUX_NAME_LEN && expected.len() > LINUX_NAME_LEN && expected.starts_with(actual))
+}
+
+// The process or one of its ancestors was started by the service.
+fn was_launched(process: &Process, processes: &HashMap<Pid, Process>, launched: &HashSet<u32>) -> bool {
+    let mut current = Some(process);
+    // The parent chain ends at the init process; the limit guards against a cycle from reused IDs.
+    for _ in 0..64 {
+        let Some(process) = current else { return false };
+        if launched.contains(&process.pid().as_u32()) {
+            return true;
+        }
+        current = process.parent().and_then(|parent| processes.get(&parent));
+    }
+    false
+}
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use super::rules::Action;
+use super::wake_event::{Outcome, WakeRecord};
+
+// The `[queue]` config section: launches requested while nobody is logged on wait for a user session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    // Without the queue such launches fail right away on Windows. Linux starts programs without
+    // a session, so there the queue is only used when enabled explicitly.
+    pub enabled: bool,
+    // How long a launch waits for somebody to log on.
+    pub expiry_secs: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig { enabled: cfg!(windows), expiry_secs: 900 }
+    }
+}
+
+// A launch waiting for a user session, as reported by the control API and `queue`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedLaunch {
+    pub rule: String,
+    // The executable to start.
+    pub action: String,
+    pub queued_at: DateTime<Local>,
+    pub expires_at: DateTime<Local>,
+}
+
+pub struct Entry {
+    pub launch: QueuedLaunch,
+    pub action: Action,
+    // The record of the wake event or API trigger that requested the launch.
+    origin: WakeRecord,
+}
+
+impl Entry {
+    // A record of what became of the queued launch, following up on the wake event that requested it.
+    pub fn report(&self, outcome: Outcome, detail: String, pid: Option<u32>) -> WakeRecord {
+        let origin = self.origin.clone();
+        let detail = match origin.detail {
+            Some(reason) => format!("{}; {}", reason, detail),
+            None => detail,
+        };
+        WakeRecord { time: Local::now(), outcome, detail: Some(detail), pid, ..origin }
+    }
+}
+
+// At most one launch per rule: a later wake for the same rule replaces the waiting one.
+#[derive(Default)]
+pub struct Queue {
+    entries: Vec<Entry>,
+}
+
+impl Queue {
+    // Also returns the launch that was waiting for the same rule, if any.
+    pub fn push(&mut self, rule: &str, action: Action, origin: WakeRecord, config: &QueueConfig) -> (QueuedLaunch, Option<Entry>) {
+        let replaced = self.remove(|entry| entry.launch.rule == rule).pop();
+        let queued_at = Local::now();
+        let expiry = chrono::Duration::seconds(i64::try_from(config.expiry_secs).unwrap_or(i64::MAX / 1000));
+        let launch = QueuedLaunch {
+            rule: rule.to_string(),
+            action: action.run_path.clone(),
+            queued_at,
+            expires_at: queued_at.checked_add_signed(expiry).unwrap_or(queued_at),
+        };
+        self.entries.push(Entry { launch: launch.clone(), action, origin });
+        (launch, replaced)
+    }
+
+    // Removes the launch waiting for `rule`, or all of them.
+    pub fn cancel(&mut self, rule: Option<&str>) -> Vec<Entry> {
+        self.remove(|entry| rule.is_none_or(|rule| entry.launch.rule == rule))
+    }
+
+    pub fn expire(&mut self) -> Vec<Entry> {
+        let now = Local::now();
+        self.remove(|entry| entry.launch.expires_at <= now)
+    }
+
+    // The launches `ready` finds a session for, in the order they were queued; the others keep waiting.
+    pub fn take<T>(&mut self, mut ready: impl FnMut(&Entry) -> Option<T>) -> Vec<(Entry, T)> {
+        let mut taken = Vec::new();
+        for entry in std::mem::take(&mut self.entries) {
+            match ready(&entry) {
+                Some(session) => taken.push((entry, session)),
+                None => self.entries.push(entry),
+            }
+        }
+        taken
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn launches(&self) -> Vec<QueuedLaunch> {
+        self.entries.iter().map(|entry| entry.launch.clone()).collect()
+    }
+
+    fn remove(&mut self, selected: impl Fn(&Entry) -> bool) -> Vec<Entry> {
+        let (removed, kept) = std::mem::take(&mut self.entries).into_iter().partition(selected);
+        self.entries = kept;
+        removed
+    }
+}
@@ -0,0 +1,301 @@
+use std::{io::{Read, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs}, path::PathBuf,
+          sync::mpsc::{self, Receiver, TryRecvError}, thread, time::{Duration, Instant}};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, PidExt, ProcessExt, ProcessStatus, System, SystemExt};
+use super::rules::Action;
+use super::wake_event::{Outcome, WakeRecord};
+
+// How often the probes are tried.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+// How long a single connection attempt may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+// How much of an HTTP response is read.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+// What to do with a program that did not become ready in time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    // Only record the failure.
+    #[default]
+    Keep,
+    // Stop the program, like a stop rule.
+    Stop,
+}
+
+// The `ready` table of a rule: how to tell that the started program works. Every probe that is
+// set has to succeed within `timeout_secs`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadyConfig {
+    // The started process still runs this many seconds later.
+    pub alive_secs: Option<u64>,
+    // host:port accepting TCP connections.
+    pub tcp: Option<String>,
+    // An http:// URL answering GET with a 2xx status.
+    pub http: Option<String>,
+    // A file the program creates once it is up.
+    pub file: Option<PathBuf>,
+    pub timeout_secs: u64,
+    pub on_failure: OnFailure,
+}
+
+impl Default for ReadyConfig {
+    fn default() -> ReadyConfig {
+        ReadyConfig { alive_secs: None, tcp: None, http: None, file: None, timeout_secs: 30, on_failure: OnFailure::Keep }
+    }
+}
+
+impl ReadyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.alive_secs.is_none() && self.tcp.is_none() && self.http.is_none() && self.file.is_none() {
+            return Err(String::from("set at least one of alive_secs, tcp, http and file"));
+        }
+        if let Some(alive_secs) = self.alive_secs.filter(|alive_secs| *alive_secs > self.timeout_secs) {
+            return Err(format!("alive_secs {} is longer than timeout_secs {}", alive_secs, self.timeout_secs));
+        }
+        if let Some(tcp) = &self.tcp {
+            split_port(tcp).ok_or_else(|| format!("tcp {} is not host:port", tcp))?;
+        }
+        if let Some(http) = &self.http {
+            parse_url(http)?;
+        }
+        Ok(())
+    }
+
+    // The probes, for messages.
+    pub fn describe(&self) -> String {
+        let mut probes = Vec::new();
+        probes.extend(self.alive_secs.map(|secs| format!("still running after {}s", secs)));
+        probes.extend(self.tcp.as_ref().map(|tcp| format!("{} accepting connections", tcp)));
+        probes.extend(self.http.as_ref().map(|http| format!("{} answering with 2xx", http)));
+        probes.extend(self.file.as_ref().map(|file| format!("{} existing", file.display())));
+        probes.join(", ")
+    }
+}
+
+// A launch whose readiness is being checked on a thread of its own.
+pub struct Pending {
+    pub rule: String,
+    pub action: Action,
+    pub pid: u32,
+    // The record of the launch, completed with the result.
+    origin: WakeRecord,
+    pub on_failure: OnFailure,
+    result: Receiver<Result<String, String>>,
+}
+
+impl Pending {
+    // Starts probing `pid` in the background.
+    pub fn start(rule: &str, action: Action, pid: u32, origin: WakeRecord, config: &ReadyConfig) -> Pending {
+        let (result_tx, result) = mpsc::channel();
+        let probe = config.clone();
+        thread::spawn(move || {
+            let _ = result_tx.send(verify(pid, &probe));
+        });
+        Pending { rule: rule.to_string(), action, pid, origin, on_failure: config.on_failure, result }
+    }
+
+    // None while the probes are still running: what became ready, or why the program did not.
+    pub fn result(&self) -> Option<Result<String, String>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(String::from("the readiness check stopped"))),
+        }
+    }
+
+    // The record of the launch, now that the probes told how it went.
+    pub fn report(&self, outcome: Outcome, detail: String) -> WakeRecord {
+        let origin = self.origin.clone();
+        let detail = match origin.detail {
+            Some(reason) => format!("{}; {}", reason, detail),
+            None => detail,
+        };
+        WakeRecord { time: Local::now(), outcome, detail: Some(detail), ..origin }
+    }
+}
+
+// Tries the probes until all succeed or `timeout_secs` passed.
+fn verify(pid: u32, config: &ReadyConfig) -> Result<String, String> {
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(config.timeout_secs);
+    let alive_for = config.alive_secs.map(Duration::from_secs);
+    let mut system = System::new();
+    loop {
+        if alive_for.is_some() && !is_alive(&mut system, pid) {
+            return Err(format!("exited after {:.1}s", started.elapsed().as_secs_f32()));
+        }
+        let mut pending = Vec::new();
+        if alive_for.is_some_and(|alive_for| started.elapsed() < alive_for) {
+            pending.push(String::from("has not run long enough"));
+        }
+        if let Some(tcp) = &config.tcp {
+            if let Err(e) = probe_tcp(tcp) {
+                pending.push(format!("{}: {}", tcp, e));
+            }
+        }
+        if let Some(http) = &config.http {
+            if let Err(e) = probe_http(http) {
+                pending.push(format!("{}: {}", http, e));
+            }
+        }
+        if let Some(file) = config.file.as_ref().filter(|file| !file.exists()) {
+            pending.push(format!("{} does not exist", file.display()));
+        }
+        if pending.is_empty() {
+            return Ok(format!("ready after {:.1}s: {}", started.elapsed().as_secs_f32(), config.describe()));
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("not ready after {}s: {}", config.timeout_secs, pending.join("; ")));
+        }
+        thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+// A zombie has exited; only its exit code is still waiting to be collected.
+fn is_alive(system: &mut System, pid: u32) -> bool {
+    let pid = Pid::from_u32(pid);
+    system.refresh_process(pid) && system.process(pid).is_some_and(|process| process.status() != ProcessStatus::Zombie)
+}
+
+fn probe_tcp(address: &str) -> Result<(), String> {
+    connect(address).map(|_| ())
+}
+
+// A plain HTTP/1.0 GET; https is not supported.
+fn probe_http(url: &str) -> Result<(), String> {
+    let (host, path) = parse_url(url)?;
+    let mut stream = connect(&host)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT)).map_err(|e| e.to_string())?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    // Only the status line matters, but reading on to the end lets the server close cleanly.
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response).map_err(|e| e.to_string())?;
+    let status_line = String::from_utf8_lossy(&response[..response.len().min(64)]);
+    let status: u16 = status_line.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+        .ok_or_else(|| String::from("no HTTP response"))?;
+    if !(200..300).contains(&status) {
+        return Err(format!("status {}", status));
+    }
+    Ok(())
+}
+
+fn connect(address: &str) -> Result<TcpStream, String> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs().map_err(|e| e.to_string())?.collect();
+    let mut error = String::from("no address");
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e.to_string(),
+        }
+    }
+    Err(error)
+}
+
+// Splits an http:// URL into host:port and path.
+fn parse_url(url: &str) -> Result<(String, String), String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("http {} does not start with http://", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(format!("http {} has no host", url));
+    }
+    let host = match split_port(host) {
+        Some(_) => host.to_string(),
+        // Otherwise the last group of an IPv6 address would be taken for the port.
+        None if host.contains(':') && !host.ends_with(']') => {
+            return Err(format!("http {} has an invalid port or an IPv6 address without brackets", url));
+        },
+        None => format!("{}:80", host),
+    };
+    Ok((host, path.to_string()))
+}
+
+// The port of host:port, also for [IPv6]:port.
+fn split_port(address: &str) -> Option<u16> {
+    let (host, port) = address.rsplit_once(':')?;
+    if host.is_empty() || (host.contains(':') && !host.ends_with(']')) {
+        return None;
+    }
+    port.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(host: &str, path: &str) -> Result<(String, String), String> {
+        Ok((host.to_string(), path.to_string()))
+    }
+
+    #[test]
+    fn splits_ports() {
+        assert_eq!(split_port("localhost:8080"), Some(8080));
+        assert_eq!(split_port("192.168.1.20:80"), Some(80));
+        assert_eq!(split_port("[::1]:8080"), Some(8080));
+        assert_eq!(split_port("localhost"), None);
+        assert_eq!(split_port(":8080"), None);
+        assert_eq!(split_port("localhost:http"), None);
+        assert_eq!(split_port("localhost:65536"), None);
+        // Without brackets the last group of an IPv6 address cannot be told from a port.
+        assert_eq!(split_port("::1"), None);
+        assert_eq!(split_port("fd00::1:8080"), None);
+        assert_eq!(split_port("[::1]"), None);
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(parse_url("http://localhost:8080/health"), pair("localhost:8080", "/health"));
+        assert_eq!(parse_url("http://[::1]:8080/health?full=1"), pair("[::1]:8080", "/health?full=1"));
+        // The port defaults to 80 and the path to /.
+        assert_eq!(parse_url("http://example.com"), pair("example.com:80", "/"));
+        assert_eq!(parse_url("http://[::1]/"), pair("[::1]:80", "/"));
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        assert!(parse_url("https://localhost/health").unwrap_err().contains("does not start with http://"));
+        assert!(parse_url("localhost:8080").is_err());
+        assert!(parse_url("http:///health").unwrap_err().contains("has no host"));
+        assert!(parse_url("http://::1/health").unwrap_err().contains("without brackets"));
+        assert!(parse_url("http://fd00::1:8080/").is_err());
+        assert!(parse_url("http://localhost:http/").is_err());
+    }
+
+    #[test]
+    fn validates_ready_tables() {
+        let ready = |alive_secs, tcp: Option<&str>, http: Option<&str>, file: Option<&str>| ReadyConfig {
+            alive_secs,
+            tcp: tcp.map(String::from),
+            http: http.map(String::from),
+            file: file.map(PathBuf::from),
+            timeout_secs: 30,
+            on_failure: OnFailure::Keep,
+        };
+        assert_eq!(ReadyConfig::default().validate(), Err(String::from("set at least one of alive_secs, tcp, http and file")));
+        assert_eq!(ready(Some(30), None, None, None).validate(), Ok(()));
+        assert_eq!(ready(Some(31), None, None, None).validate(), Err(String::from("alive_secs 31 is longer than timeout_secs 30")));
+        assert_eq!(ready(None, Some("[::1]:8080"), None, None).validate(), Ok(()));
+        assert_eq!(ready(None, Some("localhost"), None, None).validate(), Err(String::from("tcp localhost is not host:port")));
+        assert_eq!(ready(None, Some("::1"), None, None).validate(), Err(String::from("tcp ::1 is not host:port")));
+        assert_eq!(ready(None, None, Some("http://localhost/health"), None).validate(), Ok(()));
+        assert!(ready(None, None, Some("https://localhost/health"), None).validate().is_err());
+        assert_eq!(ready(None, None, None, Some("/run/kodi.ready")).validate(), Ok(()));
+    }
+
+    #[test]
+    fn reads_ready_tables() {
+        let ready: ReadyConfig = toml::from_str(r#"tcp = "localhost:8080"
+                                                   on_failure = "stop""#).unwrap();
+        assert_eq!(ready.timeout_secs, 30);
+        assert_eq!(ready.on_failure, OnFailure::Stop);
+        assert_eq!(ready.describe(), "localhost:8080 accepting connections");
+        assert!(toml::from_str::<ReadyConfig>(r#"on_failure = "restart""#).is_err());
+        assert!(toml::from_str::<ReadyConfig>("port = 8080").is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::{fmt, time::Duration};
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use super::limits::Limits;
+use super::process_match::ProcessMatch;
+use super::schedule::Schedule;
+use super::readiness::ReadyConfig;
+use super::supervisor::SuperviseConfig;
+use super::wake_event::WakeEvent;
+
+// A program to start: `program` is the process name used to check whether it already runs,
+// unless `running` says otherwise, `run_path` the executable to launch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Action {
+    pub program: String,
+    pub run_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub running: Option<ProcessMatch>,
+    // Start the program as this user, in their session, instead of the active session's user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    // Constraints for the program on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
+}
+
+impl Action {
+    pub fn new(program: &str, run_path: &str) -> Action {
+        Action { program: program.to_string(), run_path: run_path.to_string(), running: None, user: None, limits: None }
+    }
+
+    // How the running check finds the program, for messages.
+    pub fn describe_running(&self) -> String {
+        self.running.clone().unwrap_or_default().describe(&self.program)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.run_path)
+    }
+}
+
+// How long a stopped program gets to exit before it is killed.
+const DEFAULT_STOP_GRACE: Duration = Duration::from_secs(10);
+
+// What a wake does with the rule's program.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    #[default]
+    Start,
+    // Start the program, or stop it if it is running.
+    Toggle,
+    // Only stop the program, e.g. for a separate "off" MAC.
+    Stop,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Start => write!(f, "start"),
+            Mode::Toggle => write!(f, "toggle"),
+            Mode::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+// An entry of the `[[rules]]` config section. The first rule whose `target_mac` matches
+// the wake event decides what happens; without any rules every wake starts the installed program.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    // Only handle magic packets for this MAC; unset matches any target.
+    pub target_mac: Option<MacAddr>,
+    // Override the program installed with the service.
+    pub program: Option<String>,
+    pub run_path: Option<String>,
+    // How to tell that the program already runs; by default by its process name.
+    pub running: Option<ProcessMatch>,
+    // The user to start the program as: a name, a uid on Linux or a SID on Windows.
+    pub user: Option<String>,
+    // Nice level, CPU, memory and other limits for the program on Linux.
+    pub limits: Option<Limits>,
+    pub schedule: Option<Schedule>,
+    // Started instead when a wake arrives outside the schedule.
+    pub fallback: Option<Action>,
+    // Restart the started program when it crashes.
+    pub supervise: Option<SuperviseConfig>,
+    // Probes telling whether the started program came up; the launch is recorded once they decide.
+    pub ready: Option<ReadyConfig>,
+    pub mode: Mode,
+    // Defaults to DEFAULT_STOP_GRACE.
+    pub stop_grace_secs: Option<u64>,
+}
+
+impl Rule {
+    // The implicit rule used when the config file has none.
+    pub fn default_rule() -> Rule {
+        Rule { name: String::from("default"), ..Rule::default() }
+    }
+
+    pub fn matches(&self, event: &WakeEvent) -> bool {
+        self.target_mac.is_none_or(|mac| mac == event.target_mac)
+    }
+
+    pub fn stop_grace(&self) -> Duration {
+        self.stop_grace_secs.map_or(DEFAULT_STOP_GRACE, Duration::from_secs)
+    }
+
+    pub fn action(&self, default: &Action) -> Action {
+        Action {
+            program: self.program.clone().unwrap_or_else(|| default.program.clone()),
+            run_path: self.run_path.clone().unwrap_or_else(|| default.run_path.clone()),
+            running: self.running.clone(),
+            user: self.user.clone(),
+            limits: self.limits.clone(),
+        }
+    }
+
+    // The fallback runs for the rule's user unless it names its own.
+    pub fn fallback_action(&self) -> Option<Action> {
+        self.fallback.clone().map(|fallback| Action { user: fallback.user.or_else(|| self.user.clone()), ..fallback })
+    }
+}
+
+// The outcome of evaluating the rules against one wake event.
+pub enum Decision<'a> {
+    Run(&'a Rule, Action),
+    // Outside the rule's schedule; carries the reason and the fallback action, if any.
+    OutOfSchedule(&'a Rule, String, Option<Action>),
+    NoMatch,
+}
+
+pub fn evaluate<'a>(rules: &'a [Rule], event: &WakeEvent, default: &Action) -> Decision<'a> {
+    let rule = match rules.iter().find(|rule| rule.matches(event)) {
+        Some(rule) => rule,
+        None => return Decision::NoMatch,
+    };
+    if let Some(schedule) = &rule.schedule {
+        if let Some(reason) = schedule.check(&event.received_at) {
+            return Decision::OutOfSchedule(rule, reason, rule.fallback_action());
+        }
+    }
+    Decision::Run(rule, rule.action(default))
+}
+
+// Why each rule did or did not decide a wake event, in rule order, for the `monitor` subcommand.
+pub fn explain(rules: &[Rule], event: &WakeEvent) -> Vec<(String, String)> {
+    let mut decided: Option<&str> = None;
+    rules.iter().map(|rule| {
+        let explanation = match (decided, rule.target_mac) {
+            (Some(name), _) => format!("not reached, rule {} decided first", name),
+            (None, Some(mac)) if mac != event.target_mac => format!("target MAC is not {}", mac),
+            (None, _) => {
+                decided = Some(&rule.name);
+                match rule.schedule.as_ref().and_then(|schedule| schedule.check(&event.received_at)) {
+                    Some(reason) if rule.fallback.is_some() => format!("matches, but {}; fallback applies", reason),
+                    Some(reason) => format!("matches, but {}", reason),
+                    None => String::from("matches"),
+                }
+            },
+        };
+        (rule.name.clone(), explanation)
+    }).collect()
+}
+
+// Every action the rules can start, used to decide whether there is anything left to listen for.
+pub fn all_actions(rules: &[Rule], default: &Action) -> Vec<Action> {
+    let mut actions: Vec<Action> = Vec::new();
+    for rule in rules {
+        for action in std::iter::once(rule.action(default)).chain(rule.fallback.clone()) {
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use super::super::wake_event::Encapsulation;
+
+    const FIRST: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+    const SECOND: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x66);
+
+    fn rules(toml: &str) -> Vec<Rule> {
+        #[derive(Deserialize)]
+        struct Rules {
+            rules: Vec<Rule>,
+        }
+        toml::from_str::<Rules>(toml).unwrap().rules
+    }
+
+    fn default_action() -> Action {
+        Action::new("kodi", "/usr/bin/kodi")
+    }
+
+    // 2026-10-16 is a Friday.
+    fn wake(target_mac: MacAddr) -> WakeEvent {
+        WakeEvent {
+            received_at: Local.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap(),
+            interface: String::from("eth0"),
+            target_mac,
+            source_mac: MacAddr::zero(),
+            source_ip: None,
+            source_port: None,
+            encapsulation: Encapsulation::Udp(9),
+            trailer: Vec::new(),
+        }
+    }
+
+    fn decided(decision: Decision<'_>) -> (&str, Option<Action>) {
+        match decision {
+            Decision::Run(rule, action) => (rule.name.as_str(), Some(action)),
+            Decision::OutOfSchedule(rule, _, fallback) => (rule.name.as_str(), fallback),
+            Decision::NoMatch => ("", None),
+        }
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let rules = rules(&format!(r#"
+            [[rules]]
+            name = "first"
+            target_mac = "{FIRST}"
+            program = "steam"
+            run_path = "/usr/bin/steam"
+
+            [[rules]]
+            name = "shadowed"
+            target_mac = "{FIRST}"
+
+            [[rules]]
+            name = "any"
+        "#));
+        let (name, action) = decided(evaluate(&rules, &wake(FIRST), &default_action()));
+        assert_eq!(name, "first");
+        assert_eq!(action.unwrap().run_path, "/usr/bin/steam");
+        // A rule without a target matches the rest, with the installed program.
+        let (name, action) = decided(evaluate(&rules, &wake(SECOND), &default_action()));
+        assert_eq!(name, "any");
+        assert_eq!(action, Some(default_action()));
+        let explanation = explain(&rules, &wake(FIRST));
+        assert_eq!(explanation[0], (String::from("first"), String::from("matches")));
+        assert_eq!(explanation[1].1, "not reached, rule first decided first");
+    }
+
+    #[test]
+    fn a_mac_without_a_rule_matches_nothing() {
+        let rules = rules(&format!(r#"
+            [[rules]]
+            name = "first"
+            target_mac = "{FIRST}"
+        "#));
+        assert!(matches!(evaluate(&rules, &wake(SECOND), &default_action()), Decision::NoMatch));
+        assert_eq!(explain(&rules, &wake(SECOND))[0].1, format!("target MAC is not {}", FIRST));
+        assert!(matches!(evaluate(&[], &wake(FIRST), &default_action()), Decision::NoMatch));
+    }
+
+    #[test]
+    fn a_rule_outside_its_schedule_falls_back() {
+        let rules = rules(&format!(r#"
+            [[rules]]
+            name = "weekdays"
+            target_mac = "{FIRST}"
+            user = "alice"
+            schedule = {{ days = ["mon", "tue", "wed", "thu"] }}
+            fallback = {{ program = "player", run_path = "/usr/bin/player" }}
+
+            [[rules]]
+            name = "later"
+            target_mac = "{FIRST}"
+        "#));
+        match evaluate(&rules, &wake(FIRST), &default_action()) {
+            Decision::OutOfSchedule(rule, reason, Some(fallback)) => {
+                assert_eq!(rule.name, "weekdays");
+                assert_eq!(reason, "Fri is not a scheduled day");
+                assert_eq!(fallback.run_path, "/usr/bin/player");
+                // The fallback runs for the rule's user.
+                assert_eq!(fallback.user.as_deref(), Some("alice"));
+            },
+            _ => panic!("expected the fallback"),
+        }
+        // The rule still decides, so a later rule for the same target is not reached.
+        let explanation = explain(&rules, &wake(FIRST));
+        assert_eq!(explanation[0].1, "matches, but Fri is not a scheduled day; fallback applies");
+        assert_eq!(explanation[1].1, "not reached, rule weekdays decided first");
+    }
+
+    #[test]
+    fn a_rule_outside_its_schedule_without_fallback_starts_nothing() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "weekdays"
+            schedule = { days = ["mon"] }
+            fallback = { program = "player", run_path = "/usr/bin/player", user = "bob" }
+
+            [[rules]]
+            name = "friday"
+            schedule = { days = ["fri"] }
+        "#);
+        assert_eq!(rules[0].fallback_action().unwrap().user.as_deref(), Some("bob"));
+        let rules = &rules[1..];
+        assert!(matches!(evaluate(rules, &wake(FIRST), &default_action()), Decision::Run(..)));
+        let monday = WakeEvent { received_at: Local.with_ymd_and_hms(2026, 10, 19, 12, 0, 0).unwrap(), ..wake(FIRST) };
+        assert!(matches!(evaluate(rules, &monday, &default_action()), Decision::OutOfSchedule(_, _, None)));
+    }
+
+    #[test]
+    fn reads_modes_and_stop_grace() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "start"
+
+            [[rules]]
+            name = "toggle"
+            mode = "toggle"
+            stop_grace_secs = 3
+
+            [[rules]]
+            name = "stop"
+            mode = "stop"
+        "#);
+        let modes: Vec<Mode> = rules.iter().map(|rule| rule.mode).collect();
+        assert_eq!(modes, [Mode::Start, Mode::Toggle, Mode::Stop]);
+        assert_eq!(rules[1].mode.to_string(), "toggle");
+        assert_eq!(rules[0].stop_grace(), DEFAULT_STOP_GRACE);
+        assert_eq!(rules[1].stop_grace(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn lists_every_action_once() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "one"
+            fallback = { program = "player", run_path = "/usr/bin/player" }
+
+            [[rules]]
+            name = "two"
+            fallback = { program = "player", run_path = "/usr/bin/player" }
+        "#);
+        let actions = all_actions(&rules, &default_action());
+        let run_paths: Vec<&str> = actions.iter().map(|action| action.run_path.as_str()).collect();
+        assert_eq!(run_paths, ["/usr/bin/kodi", "/usr/bin/player"]);
+    }
+}
@@ -24,9 +24,8 @@ use winapi::um::{
 };
 use winapi::ctypes::c_void;
 
-fn get_session_user_token() -> Option<HANDLE> {
+fn get_session_user_token(session_id: u32) -> Option<HANDLE> {
     let mut user_token: HANDLE = null_mut();
-    let session_id = unsafe { WTSGetActiveConsoleSessionId() };
     if unsafe { WTSQueryUserToken(session_id, &mut user_token) } != 0 {
         Some(user_token)
     } else {
@@ -34,8 +33,41 @@ fn get_session_user_token() -> Option<HANDLE> {
     }
 }
 
+// The environment a program started in the session gets, as name and value pairs.
+pub fn session_environment(session_id: u32) -> Option<Vec<(String, String)>> {
+    let h_user_token = get_session_user_token(session_id)?;
+    let mut env: *mut c_void = null_mut();
+    let created = unsafe { CreateEnvironmentBlock(&mut env, h_user_token, 0) } != 0;
+    unsafe { CloseHandle(h_user_token) };
+    if !created {
+        return None;
+    }
+    // NAME=value strings, each ending in a zero, with another zero after the last.
+    let mut environment = Vec::new();
+    let mut cursor = env as *const u16;
+    loop {
+        let length = (0..).take_while(|&i| unsafe { *cursor.add(i) } != 0).count();
+        if length == 0 {
+            break;
+        }
+        let variable = String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(cursor, length) });
+        // Names starting with '=' hold the current directory per drive.
+        if let Some((key, value)) = variable.split_once('=').filter(|(key, _)| !key.is_empty()) {
+            environment.push((key.to_string(), value.to_string()));
+        }
+        cursor = unsafe { cursor.add(length + 1) };
+    }
+    unsafe { DestroyEnvironmentBlock(env) };
+    Some(environment)
+}
+
 pub fn start_process_as_current_user(app_path: &str, cmd_line: Option<&str>, work_dir: Option<&str>, visible: bool) -> Result<u32, i32> {
-    let h_user_token = match get_session_user_token() {
+    let session_id = unsafe { WTSGetActiveConsoleSessionId() };
+    start_process_in_session(session_id, app_path, cmd_line, work_dir, visible)
+}
+
+pub fn start_process_in_session(session_id: u32, app_path: &str, cmd_line: Option<&str>, work_dir: Option<&str>, visible: bool) -> Result<u32, i32> {
+    let h_user_token = match get_session_user_token(session_id) {
         Some(token) => token,
         None => return Err(-1)
     };
@@ -0,0 +1,191 @@
+use std::fmt;
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+// When a rule may fire, evaluated in the machine's local time zone.
+// Empty lists place no restriction, so an empty schedule always matches.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    // e.g. ["mon", "tue", "wed", "thu", "fri"]
+    pub days: Vec<Weekday>,
+    // e.g. ["07:00-12:00", "14:00-21:00"]; a range may wrap past midnight.
+    pub times: Vec<TimeRange>,
+    // Dates on which the rule never fires, e.g. school holidays.
+    pub exclude_dates: Vec<NaiveDate>,
+}
+
+impl Schedule {
+    // Returns why the time is outside the schedule, or None if it is inside. The part of a range
+    // after midnight belongs to the day the range started on, so `days = ["fri"]` with
+    // "22:00-02:00" matches early on Saturday but not early on Friday.
+    pub fn check(&self, time: &DateTime<Local>) -> Option<String> {
+        let (date, now) = (time.date_naive(), time.time());
+        if self.times.is_empty() {
+            return self.check_day(date);
+        }
+        let mut reason = None;
+        for range in &self.times {
+            let Some(day) = range.start_day(date, now) else { continue };
+            match self.check_day(day) {
+                None => return None,
+                Some(day_reason) if day != date => {
+                    reason.get_or_insert(format!("{}, where range {} started", day_reason, range));
+                },
+                Some(day_reason) => {
+                    reason.get_or_insert(day_reason);
+                },
+            }
+        }
+        Some(reason.unwrap_or_else(|| format!("{} is outside the scheduled times", now.format("%H:%M"))))
+    }
+
+    fn check_day(&self, date: NaiveDate) -> Option<String> {
+        if self.exclude_dates.contains(&date) {
+            return Some(format!("{} is an excluded date", date));
+        }
+        if !self.days.is_empty() && !self.days.contains(&date.weekday()) {
+            return Some(format!("{} is not a scheduled day", date.weekday()));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeRange {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+
+    // The day the range containing `time` on `date` started: the day before for the part of
+    // a wrapping range after midnight. None when the range does not contain the time.
+    pub fn start_day(&self, date: NaiveDate, time: NaiveTime) -> Option<NaiveDate> {
+        if !self.contains(time) {
+            return None;
+        }
+        if self.from > self.to && time < self.to {
+            return date.pred_opt();
+        }
+        Some(date)
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<TimeRange, String> {
+        let (from, to) = value.split_once('-')
+            .ok_or_else(|| format!("invalid time range {:?}, expected HH:MM-HH:MM", value))?;
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map_err(|e| format!("invalid time {:?} in range {:?}: {}", time, value, e));
+        Ok(TimeRange { from: parse(from)?, to: parse(to)? })
+    }
+}
+
+impl From<TimeRange> for String {
+    fn from(range: TimeRange) -> String {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.from.format("%H:%M"), self.to.format("%H:%M"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn schedule(days: &[Weekday], times: &[&str], exclude_dates: &[&str]) -> Schedule {
+        Schedule {
+            days: days.to_vec(),
+            times: times.iter().map(|range| TimeRange::try_from(range.to_string()).unwrap()).collect(),
+            exclude_dates: exclude_dates.iter().map(|date| date.parse().unwrap()).collect(),
+        }
+    }
+
+    // 2026-10-16 is a Friday.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 10, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn empty_schedule_always_matches() {
+        assert_eq!(schedule(&[], &[], &[]).check(&at(16, 3, 0)), None);
+    }
+
+    #[test]
+    fn checks_days_and_times() {
+        let schedule = schedule(&[Weekday::Mon, Weekday::Fri], &["07:00-12:00"], &[]);
+        assert_eq!(schedule.check(&at(16, 7, 0)), None);
+        assert_eq!(schedule.check(&at(16, 11, 59)), None);
+        assert_eq!(schedule.check(&at(16, 12, 0)), Some(String::from("12:00 is outside the scheduled times")));
+        assert_eq!(schedule.check(&at(17, 8, 0)), Some(String::from("Sat is not a scheduled day")));
+    }
+
+    #[test]
+    fn excludes_dates() {
+        let schedule = schedule(&[], &[], &["2026-10-16"]);
+        assert_eq!(schedule.check(&at(16, 8, 0)), Some(String::from("2026-10-16 is an excluded date")));
+        assert_eq!(schedule.check(&at(17, 8, 0)), None);
+    }
+
+    #[test]
+    fn wrapping_range_without_days() {
+        let schedule = schedule(&[], &["22:00-02:00"], &[]);
+        assert_eq!(schedule.check(&at(16, 22, 0)), None);
+        assert_eq!(schedule.check(&at(17, 1, 59)), None);
+        assert_eq!(schedule.check(&at(17, 2, 0)), Some(String::from("02:00 is outside the scheduled times")));
+        assert_eq!(schedule.check(&at(16, 21, 59)), Some(String::from("21:59 is outside the scheduled times")));
+    }
+
+    #[test]
+    fn after_midnight_belongs_to_the_day_the_range_started() {
+        let schedule = schedule(&[Weekday::Fri], &["22:00-02:00"], &[]);
+        assert_eq!(schedule.check(&at(16, 23, 0)), None);
+        // Saturday 01:00 is still Friday night.
+        assert_eq!(schedule.check(&at(17, 1, 0)), None);
+        // Friday 01:00 is Thursday night.
+        assert_eq!(schedule.check(&at(16, 1, 0)),
+                   Some(String::from("Thu is not a scheduled day, where range 22:00-02:00 started")));
+        assert_eq!(schedule.check(&at(17, 23, 0)), Some(String::from("Sat is not a scheduled day")));
+    }
+
+    #[test]
+    fn after_midnight_checks_the_excluded_date_the_range_started() {
+        let schedule = schedule(&[], &["22:00-02:00"], &["2026-10-16"]);
+        assert_eq!(schedule.check(&at(16, 23, 0)), Some(String::from("2026-10-16 is an excluded date")));
+        assert_eq!(schedule.check(&at(17, 1, 0)),
+                   Some(String::from("2026-10-16 is an excluded date, where range 22:00-02:00 started")));
+        // Friday 01:00 belongs to Thursday, which is not excluded.
+        assert_eq!(schedule.check(&at(16, 1, 0)), None);
+    }
+
+    #[test]
+    fn any_range_can_match() {
+        let schedule = schedule(&[Weekday::Sat], &["22:00-02:00", "00:30-03:00"], &[]);
+        // Saturday 01:00: the first range started on Friday, the second on Saturday.
+        assert_eq!(schedule.check(&at(17, 1, 0)), None);
+    }
+
+    #[test]
+    fn parses_and_prints_ranges() {
+        let range = TimeRange::try_from(String::from("7:05 - 21:30")).unwrap();
+        assert_eq!(range.to_string(), "07:05-21:30");
+        assert!(TimeRange::try_from(String::from("07:00")).is_err());
+        assert!(TimeRange::try_from(String::from("07:00-25:00")).is_err());
+    }
+}
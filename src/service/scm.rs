@@ -0,0 +1,135 @@
+use std::{env, ffi::OsString, sync::{Arc, Mutex, mpsc}, time::Duration};
+use log::{debug, error, info};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher, Result,
+};
+use windows_sys::Win32::Foundation::ERROR_FAILED_SERVICE_CONTROLLER_CONNECT;
+use super::{control::Control, run_from_arguments, SERVICE_NAME};
+
+pub type StatusHandle = ServiceStatusHandle;
+
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+pub fn run() -> Result<()> {
+    info!("Starting the service...");
+    // Register generated `ffi_service_main` with the system and start the service, blocking
+    // this thread until the service is stopped.
+    match service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+        Ok(_) => {
+            info!("Service stopped successfully.");
+            Ok(())
+        },
+        Err(windows_service::Error::Winapi(e)) if e.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT as i32) => {
+            error!("Not started by the service manager; use `run` to run the service in a terminal");
+            Err(windows_service::Error::Winapi(e))
+        },
+        Err(e) => {
+            error!("Service stopped with error: {:?}", e);
+            Err(e)
+        }
+    }
+}
+
+// Generate the windows service boilerplate.
+// The boilerplate contains the low-level service entry function (ffi_service_main) that parses
+// incoming service arguments into Vec<OsString> and passes them to user defined service
+// entry (wol_service_main).
+define_windows_service!(ffi_service_main, wol_service_main);
+
+pub fn wol_service_main(_: Vec<OsString>) {
+    let arguments: Vec<String> = env::args().collect();
+    // Create a channel to be able to poll a stop event from the service worker loop.
+    let (shutdown_tx, shutdown_rx) = mpsc::channel();
+    let shutdown_rx = Arc::new(Mutex::new(shutdown_rx));
+    let (control_tx, control_rx) = mpsc::channel();
+    let reload_tx = control_tx.clone();
+
+    // Define system service event handler that will be receiving service events.
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        match control_event {
+            // Notifies a service to report its current status information to the service
+            // control manager. Always return NoError even if not implemented.
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+
+            // Handle stop
+            ServiceControl::Stop => {
+                shutdown_tx.send(()).unwrap();
+                ServiceControlHandlerResult::NoError
+            }
+
+            // `sc control wol_service paramchange`: re-read the config file.
+            ServiceControl::ParamChange => {
+                info!("Received a parameter change, reloading the config");
+                // Nobody waits for the reply; the worker loop logs the result.
+                let (reply_tx, _) = mpsc::channel();
+                let _ = reload_tx.send(Control::Reload(reply_tx));
+                ServiceControlHandlerResult::NoError
+            }
+
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    // Register system service event handler.
+    // The returned status handle should be used to report service status changes to the system.
+    let status_handle: Option<ServiceStatusHandle> =
+        match service_control_handler::register(SERVICE_NAME, event_handler) {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                error!("Failed to register the service control handler: {:?}", e);
+                None
+            }
+        };
+
+    // Tell the system that service is running
+    set_status(&status_handle, "Service status set to RUNNING", ServiceState::Running, 0);
+
+    debug!("Service entry function called with arguments: {:?}", arguments);
+    // Failures have been reported to the SCM already.
+    let _ = run_from_arguments(arguments.get(1..).unwrap_or_default(), false, shutdown_rx, (control_tx, control_rx), status_handle);
+}
+
+pub fn report_stopped(status_handle: &Option<StatusHandle>, message: &str, exit: u32) {
+    match status_handle {
+        Some(_) => set_status(status_handle, message, ServiceState::Stopped, exit),
+        // Not started by the SCM, e.g. by `run`: the log is all there is.
+        None => match exit {
+            0 => debug!("{}", message),
+            _ => error!("{}", message),
+        },
+    }
+}
+
+fn set_status(status_handle: &Option<ServiceStatusHandle>, message: &str, state: ServiceState, exit: u32) {
+    if let Some(handle) = status_handle { // Use pattern matching here
+        let control_accepted = match state {
+            ServiceState::Stopped => ServiceControlAccept::empty(),
+            _ => ServiceControlAccept::STOP | ServiceControlAccept::PARAM_CHANGE,
+        };
+        let status = ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted: control_accepted,
+            exit_code: ServiceExitCode::Win32(exit),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        };
+        match handle.set_service_status(status) { // Use the handle here
+            Ok(_) => {
+                match exit {
+                    0 => { debug!("{}", message); },
+                    _ => { error!("{}", message); }
+                }
+            },
+            Err(e) => {
+                error!("Failed to set service status. Error: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// A logged-on user session that programs can be started in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: String,
+    pub user: String,
+    // What a program started as the user needs to reach their display and session bus.
+    #[cfg(unix)]
+    pub environment: Vec<(String, String)>,
+}
+
+// The session a program for `user` starts in: one of that user's sessions, or without a user
+// the active one, as the launcher has always used.
+pub fn find(user: Option<&str>) -> Option<Session> {
+    match user {
+        Some(user) => find_user(user),
+        None => active(),
+    }
+}
+
+// The session of the user logged on to the console, if anybody is.
+#[cfg(windows)]
+pub fn active() -> Option<Session> {
+    use winapi::um::winbase::WTSGetActiveConsoleSessionId;
+
+    // 0xFFFFFFFF while sessions are being switched.
+    let id = unsafe { WTSGetActiveConsoleSessionId() };
+    if id == u32::MAX {
+        return None;
+    }
+    // The console session exists before anybody logs on, just without a user.
+    let user = query(id, windows_sys::Win32::System::RemoteDesktop::WTSUserName).filter(|user| !user.is_empty())?;
+    Some(Session { id: id.to_string(), user })
+}
+
+// `user` is a name, DOMAIN\name or a SID. A session the user switched away from counts too;
+// programs started there show once they switch back.
+#[cfg(windows)]
+fn find_user(user: &str) -> Option<Session> {
+    use windows_sys::Win32::System::RemoteDesktop::{
+        WTSActive, WTSDisconnected, WTSDomainName, WTSEnumerateSessionsW, WTSFreeMemory, WTSUserName,
+        WTS_CURRENT_SERVER_HANDLE, WTS_SESSION_INFOW,
+    };
+
+    let mut sessions: *mut WTS_SESSION_INFOW = std::ptr::null_mut();
+    let mut count = 0;
+    if unsafe { WTSEnumerateSessionsW(WTS_CURRENT_SERVER_HANDLE, 0, 1, &mut sessions, &mut count) } == 0 {
+        return None;
+    }
+    let states: Vec<(u32, bool)> = unsafe { std::slice::from_raw_parts(sessions, count as usize) }.iter()
+        .filter(|session| session.State == WTSActive || session.State == WTSDisconnected)
+        .map(|session| (session.SessionId, session.State == WTSActive))
+        .collect();
+    unsafe { WTSFreeMemory(sessions.cast()) };
+    states.into_iter()
+        .filter_map(|(id, active)| {
+            let name = query(id, WTSUserName).filter(|name| !name.is_empty())?;
+            let domain = query(id, WTSDomainName).unwrap_or_default();
+            let matches = if user.starts_with("S-1-") {
+                session_sid(id).is_some_and(|sid| sid.eq_ignore_ascii_case(user))
+            } else if user.contains('\\') {
+                format!("{}\\{}", domain, name).eq_ignore_ascii_case(user)
+            } else {
+                name.eq_ignore_ascii_case(user)
+            };
+            matches.then(|| (active, Session { id: id.to_string(), user: name }))
+        })
+        // The session on the console first.
+        .max_by_key(|(active, _)| *active)
+        .map(|(_, session)| session)
+}
+
+#[cfg(windows)]
+fn query(id: u32, class: windows_sys::Win32::System::RemoteDesktop::WTS_INFO_CLASS) -> Option<String> {
+    use windows_sys::Win32::System::RemoteDesktop::{WTSFreeMemory, WTSQuerySessionInformationW, WTS_CURRENT_SERVER_HANDLE};
+
+    let mut buffer = std::ptr::null_mut();
+    let mut bytes = 0;
+    if unsafe { WTSQuerySessionInformationW(WTS_CURRENT_SERVER_HANDLE, id, class, &mut buffer, &mut bytes) } == 0 {
+        return None;
+    }
+    // A UTF-16 string including its terminating zero.
+    let value = unsafe { std::slice::from_raw_parts(buffer, (bytes as usize / 2).saturating_sub(1)) };
+    let value = String::from_utf16_lossy(value);
+    unsafe { WTSFreeMemory(buffer.cast()) };
+    Some(value)
+}
+
+// The SID of the user logged on to the session, e.g. S-1-5-21-...-1001.
+#[cfg(windows)]
+fn session_sid(id: u32) -> Option<String> {
+    use winapi::shared::sddl::ConvertSidToStringSidW;
+    use winapi::um::{handleapi::CloseHandle, securitybaseapi::GetTokenInformation, winbase::LocalFree,
+                     winnt::{TokenUser, HANDLE, TOKEN_USER}, wtsapi32::WTSQueryUserToken};
+
+    let mut token: HANDLE = std::ptr::null_mut();
+    if unsafe { WTSQueryUserToken(id, &mut token) } == 0 {
+        return None;
+    }
+    let mut length = 0;
+    unsafe { GetTokenInformation(token, TokenUser, std::ptr::null_mut(), 0, &mut length) };
+    // u64 keeps the buffer aligned for TOKEN_USER.
+    let mut buffer = vec![0u64; (length as usize).div_ceil(8)];
+    let read = unsafe { GetTokenInformation(token, TokenUser, buffer.as_mut_ptr().cast(), length, &mut length) } != 0;
+    unsafe { CloseHandle(token) };
+    if !read {
+        return None;
+    }
+    let sid = unsafe { (*buffer.as_ptr().cast::<TOKEN_USER>()).User.Sid };
+    let mut string = std::ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(sid, &mut string) } == 0 {
+        return None;
+    }
+    let length = (0..).take_while(|&i| unsafe { *string.add(i) } != 0).count();
+    let value = String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(string, length) });
+    unsafe { LocalFree(string.cast()) };
+    Some(value)
+}
+
+// The active session on a seat, from logind's records. Without logind there is no telling,
+// so the service's own user counts as logged on.
+#[cfg(unix)]
+pub fn active() -> Option<Session> {
+    if !std::path::Path::new(LOGIND_SESSIONS).is_dir() {
+        let user = std::env::var("USER").unwrap_or_else(|_| String::from("root"));
+        return Some(Session { id: String::new(), user, environment: Vec::new() });
+    }
+    logind_sessions().into_iter()
+        .find(|session| session.active && session.seat)
+        .map(|session| session.session)
+}
+
+#[cfg(unix)]
+const LOGIND_SESSIONS: &str = "/run/systemd/sessions";
+
+// A session of `user`, a name or uid: from logind if it runs, otherwise a process of the user
+// that has a display in its environment.
+#[cfg(unix)]
+fn find_user(user: &str) -> Option<Session> {
+    let account = Account::lookup(user)?;
+    if std::path::Path::new(LOGIND_SESSIONS).is_dir() {
+        return logind_sessions().into_iter()
+            .filter(|session| session.uid == account.uid)
+            // The session in front first, then graphical ones the user switched away from.
+            .max_by_key(|session| (session.active, session.seat))
+            .map(|session| session.session);
+    }
+    proc_session(&account)
+}
+
+#[cfg(unix)]
+struct LogindSession {
+    session: Session,
+    uid: u32,
+    active: bool,
+    // On a seat, i.e. local and usually graphical, rather than e.g. over SSH.
+    seat: bool,
+}
+
+#[cfg(unix)]
+fn logind_sessions() -> Vec<LogindSession> {
+    use std::fs;
+
+    let Ok(entries) = fs::read_dir(LOGIND_SESSIONS) else { return Vec::new() };
+    entries.filter_map(|entry| entry.ok())
+        // logind keeps a .ref file per session next to the record.
+        .filter(|entry| entry.path().extension().is_none())
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok().map(|content| (entry.file_name(), content)))
+        .filter_map(|(id, content)| {
+            let value = |key: &str| content.lines()
+                .find_map(|line| line.strip_prefix(key).and_then(|line| line.strip_prefix('=')))
+                .unwrap_or("");
+            if value("CLASS") != "user" {
+                return None;
+            }
+            let uid: u32 = value("UID").parse().ok()?;
+            // logind does not record the session's environment; these are where desktops put things.
+            let runtime_dir = format!("/run/user/{}", uid);
+            let mut environment = vec![
+                (String::from("XDG_RUNTIME_DIR"), runtime_dir.clone()),
+                (String::from("DBUS_SESSION_BUS_ADDRESS"), format!("unix:path={}/bus", runtime_dir)),
+            ];
+            if !value("DISPLAY").is_empty() {
+                environment.push((String::from("DISPLAY"), value("DISPLAY").to_string()));
+            }
+            if value("TYPE") == "wayland" {
+                environment.push((String::from("WAYLAND_DISPLAY"), String::from("wayland-0")));
+            }
+            Some(LogindSession {
+                session: Session { id: id.to_string_lossy().into_owned(), user: value("USER").to_string(), environment },
+                uid,
+                active: value("ACTIVE") == "1",
+                seat: !value("SEAT").is_empty(),
+            })
+        })
+        .collect()
+}
+
+// Without logind: borrow the environment of a process of the user that runs on a display.
+#[cfg(unix)]
+fn proc_session(account: &Account) -> Option<Session> {
+    use std::{fs, os::unix::fs::MetadataExt};
+
+    const SESSION_VARIABLES: [&str; 5] = ["DISPLAY", "WAYLAND_DISPLAY", "XDG_RUNTIME_DIR", "DBUS_SESSION_BUS_ADDRESS",
+                                          "XAUTHORITY"];
+    fs::read_dir("/proc").ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter(|entry| entry.metadata().is_ok_and(|metadata| metadata.uid() == account.uid))
+        .find_map(|entry| {
+            let environ = fs::read(entry.path().join("environ")).ok()?;
+            let environment: Vec<(String, String)> = environ.split(|&byte| byte == 0)
+                .filter_map(|variable| std::str::from_utf8(variable).ok()?.split_once('='))
+                .filter(|(key, _)| SESSION_VARIABLES.contains(key))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            let display = environment.iter().any(|(key, _)| key == "DISPLAY" || key == "WAYLAND_DISPLAY");
+            display.then(|| Session { id: entry.file_name().to_string_lossy().into_owned(), user: account.name.clone(),
+                                      environment })
+        })
+}
+
+// A user account from the passwd database.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+}
+
+#[cfg(unix)]
+impl Account {
+    // `user` is a name or a numeric uid.
+    pub fn lookup(user: &str) -> Option<Account> {
+        use std::ffi::{CStr, CString};
+
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; 16384];
+        let status = match user.parse::<u32>() {
+            Ok(uid) => unsafe {
+                libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+            },
+            Err(_) => {
+                let name = CString::new(user).ok()?;
+                unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) }
+            },
+        };
+        if status != 0 || result.is_null() {
+            return None;
+        }
+        let string = |pointer: *const libc::c_char| unsafe { CStr::from_ptr(pointer) }.to_string_lossy().into_owned();
+        Some(Account { name: string(passwd.pw_name), uid: passwd.pw_uid, gid: passwd.pw_gid, home: string(passwd.pw_dir) })
+    }
+}
@@ -0,0 +1,76 @@
+use std::{collections::VecDeque, fmt, sync::{Arc, Mutex}};
+use chrono::{DateTime, Local};
+use pnet::datalink::NetworkInterface;
+use serde::{Deserialize, Serialize};
+use super::queue::QueuedLaunch;
+use super::rules::Rule;
+use super::wake_event::WakeRecord;
+
+// How many wake events are kept in memory for the control API.
+const RECENT_EVENTS_LEN: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    Starting,
+    // Capturing packets because at least one program is not running.
+    Listening,
+    // Every program the rules can start is already running.
+    Idle,
+    Stopping,
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Activity::Starting => write!(f, "starting"),
+            Activity::Listening => write!(f, "listening"),
+            Activity::Idle => write!(f, "idle"),
+            Activity::Stopping => write!(f, "stopping"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramStatus {
+    pub program: String,
+    pub running: bool,
+}
+
+// What the running service is doing, shared between the worker loop and the control API.
+#[derive(Debug, Clone, Serialize)]
+pub struct State {
+    pub activity: Activity,
+    pub started_at: DateTime<Local>,
+    pub config_path: Option<String>,
+    pub interfaces: Vec<NetworkInterface>,
+    pub rules: Vec<Rule>,
+    pub programs: Vec<ProgramStatus>,
+    // Launches waiting for somebody to log on.
+    pub queue: Vec<QueuedLaunch>,
+    pub recent_events: VecDeque<WakeRecord>,
+}
+
+pub type SharedState = Arc<Mutex<State>>;
+
+impl State {
+    pub fn new(config_path: Option<String>) -> State {
+        State {
+            activity: Activity::Starting,
+            started_at: Local::now(),
+            config_path,
+            interfaces: Vec::new(),
+            rules: Vec::new(),
+            programs: Vec::new(),
+            queue: Vec::new(),
+            recent_events: VecDeque::with_capacity(RECENT_EVENTS_LEN),
+        }
+    }
+
+    pub fn record(&mut self, record: WakeRecord) {
+        if self.recent_events.len() == RECENT_EVENTS_LEN {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(record);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{thread, time::{Duration, Instant}};
+use log::{info, warn};
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+
+// How often a stopping program is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Asks the processes to exit and kills those still running after `grace`, on a thread of its own
+// so the worker loop keeps handling packets meanwhile.
+pub fn stop(program: &str, pids: Vec<u32>, grace: Duration) {
+    let program = program.to_string();
+    thread::spawn(move || {
+        if let Err(e) = request_exit(&pids) {
+            warn!(program = program.as_str(); "Could not ask {} to exit, killing it after the grace period: {}", program, e);
+        }
+        let deadline = Instant::now() + grace;
+        let mut system = System::new();
+        let mut remaining = pids;
+        loop {
+            remaining.retain(|pid| system.refresh_process(Pid::from_u32(*pid)));
+            if remaining.is_empty() {
+                info!(program = program.as_str(); "{} exited", program);
+                return;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        for pid in &remaining {
+            if system.process(Pid::from_u32(*pid)).is_some_and(|process| process.kill()) {
+                warn!(program = program.as_str(), pid; "{} did not exit within {}s, killed process {}",
+                      program, grace.as_secs(), pid);
+            } else {
+                warn!(program = program.as_str(), pid; "Failed to kill process {} of {}", pid, program);
+            }
+        }
+    });
+}
+
+// The process and every process it started in turn that still runs. Children of a process that
+// already exited have lost their parent and are not found.
+pub fn tree(root: u32) -> Vec<u32> {
+    let mut system = System::new();
+    system.refresh_processes();
+    let processes = system.processes();
+    let root = Pid::from_u32(root);
+    let mut tree = if processes.contains_key(&root) { vec![root] } else { Vec::new() };
+    let mut next = 0;
+    while next < tree.len() {
+        let parent = tree[next];
+        tree.extend(processes.iter().filter(|(_, process)| process.parent() == Some(parent)).map(|(pid, _)| *pid));
+        next += 1;
+    }
+    tree.into_iter().map(|pid| pid.as_u32()).collect()
+}
+
+#[cfg(unix)]
+fn request_exit(pids: &[u32]) -> Result<(), String> {
+    let mut system = System::new();
+    for pid in pids {
+        let pid = Pid::from_u32(*pid);
+        if system.refresh_process(pid) {
+            let sent = system.process(pid).and_then(|process| process.kill_with(sysinfo::Signal::Term));
+            if sent != Some(true) {
+                return Err(format!("failed to send SIGTERM to process {}", pid));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The service's session cannot reach the user's windows, so `taskkill` without /F posts
+// WM_CLOSE to them from inside the session the program runs in.
+#[cfg(windows)]
+fn request_exit(pids: &[u32]) -> Result<(), String> {
+    use winapi::um::{processthreadsapi::ProcessIdToSessionId, winbase::WTSGetActiveConsoleSessionId};
+    let mut session_id = 0;
+    if pids.first().is_none_or(|pid| unsafe { ProcessIdToSessionId(*pid, &mut session_id) } == 0) {
+        session_id = unsafe { WTSGetActiveConsoleSessionId() };
+    }
+    let system_root = std::env::var("SystemRoot").unwrap_or_else(|_| String::from("C:\\Windows"));
+    let taskkill = format!("{}\\System32\\taskkill.exe", system_root);
+    let command_line = pids.iter().fold(String::from("taskkill.exe"), |line, pid| format!("{} /PID {}", line, pid));
+    super::run_as_current_user::start_process_in_session(session_id, &taskkill, Some(&command_line), None, false)
+        .map(|_| ())
+        .map_err(|e| format!("failed to run taskkill in session {}, error {}", session_id, e))
+}
@@ -0,0 +1,374 @@
+use std::{collections::{HashMap, HashSet}, fmt, time::{Duration, Instant}};
+use chrono::Local;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+use super::rules::{Action, Rule};
+use super::wake_event::{Outcome, WakeRecord};
+
+// Supervised programs are checked at most this often.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Restart {
+    // Only after the program exits with an error or is killed.
+    OnFailure,
+    // Whenever the program and everything it started are gone.
+    Always,
+}
+
+// The `supervise` table of a rule: keep the started program running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuperviseConfig {
+    pub restart: Restart,
+    // Restarts in a row before giving up; a run of `stable_secs` resets the count.
+    pub max_restarts: u32,
+    // Delay before the first restart, doubled for each further one up to `max_backoff_secs`.
+    pub backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub stable_secs: u64,
+}
+
+impl Default for SuperviseConfig {
+    fn default() -> SuperviseConfig {
+        SuperviseConfig { restart: Restart::OnFailure, max_restarts: 5, backoff_secs: 1, max_backoff_secs: 300,
+                          stable_secs: 600 }
+    }
+}
+
+impl SuperviseConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_backoff_secs < self.backoff_secs {
+            return Err(format!("max_backoff_secs {} is less than backoff_secs {}", self.max_backoff_secs, self.backoff_secs));
+        }
+        Ok(())
+    }
+
+    // The delay before restart number `restarts + 1`.
+    fn backoff(&self, restarts: u32) -> Duration {
+        let secs = self.backoff_secs.saturating_mul(2u64.saturating_pow(restarts)).min(self.max_backoff_secs);
+        Duration::from_secs(secs)
+    }
+}
+
+// How a launched process ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Code(i32),
+    #[cfg(unix)]
+    Signal(i32),
+    // The process could not be opened to read its exit code.
+    Unknown,
+}
+
+impl Exit {
+    // An unknown exit counts as a normal one, so a program the user closed is not brought back.
+    fn failed(self) -> bool {
+        !matches!(self, Exit::Code(0) | Exit::Unknown)
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::Code(code) => write!(f, "exited with code {}", code),
+            #[cfg(unix)]
+            Exit::Signal(signal) => write!(f, "was killed by signal {}", signal),
+            Exit::Unknown => write!(f, "exited"),
+        }
+    }
+}
+
+// A started program, so its exit status can be collected.
+#[cfg(unix)]
+pub struct Process(Option<std::process::Child>);
+
+#[cfg(unix)]
+impl Process {
+    pub fn new(child: std::process::Child) -> Process {
+        Process(Some(child))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.as_ref().map_or(0, |child| child.id())
+    }
+
+    // None while the process runs.
+    fn try_exit(&mut self) -> Option<Exit> {
+        use std::os::unix::process::ExitStatusExt;
+        let status = self.0.as_mut()?.try_wait().ok()??;
+        Some(match (status.code(), status.signal()) {
+            (Some(code), _) => Exit::Code(code),
+            (None, Some(signal)) => Exit::Signal(signal),
+            (None, None) => Exit::Unknown,
+        })
+    }
+}
+
+// Reap an unsupervised program once it exits so it does not linger as a zombie.
+#[cfg(unix)]
+impl Drop for Process {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.0.take() {
+            if matches!(child.try_wait(), Ok(None)) {
+                std::thread::spawn(move || child.wait());
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+pub struct Process {
+    pid: u32,
+    handle: winapi::um::winnt::HANDLE,
+}
+
+#[cfg(windows)]
+impl Process {
+    // The process is opened right after it was started, before its ID can be reused.
+    pub fn open(pid: u32) -> Process {
+        use winapi::um::{processthreadsapi::OpenProcess, winnt::PROCESS_QUERY_LIMITED_INFORMATION};
+        let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+        if handle.is_null() {
+            warn!("Cannot open process {} to read its exit code: {}", pid, std::io::Error::last_os_error());
+        }
+        Process { pid, handle }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    // None while the process runs. Without a handle only the process list tells that it ended.
+    fn try_exit(&mut self) -> Option<Exit> {
+        use winapi::um::{minwinbase::STILL_ACTIVE, processthreadsapi::GetExitCodeProcess};
+        if self.handle.is_null() {
+            return None;
+        }
+        let mut code = 0;
+        if unsafe { GetExitCodeProcess(self.handle, &mut code) } == 0 {
+            return Some(Exit::Unknown);
+        }
+        match code {
+            STILL_ACTIVE => None,
+            code => Some(Exit::Code(code as i32)),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Process {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { winapi::um::handleapi::CloseHandle(self.handle) };
+        }
+    }
+}
+
+// A supervised program: the process that was started and every process it started in turn.
+struct Supervised {
+    action: Action,
+    config: SuperviseConfig,
+    // None once it exited and its exit was collected.
+    process: Option<Process>,
+    exit: Option<Exit>,
+    tree: HashSet<Pid>,
+    started_at: Instant,
+    restarts: u32,
+    restart_at: Option<Instant>,
+    // The record of the launch, whose source the crash and restart records repeat.
+    origin: WakeRecord,
+}
+
+impl Supervised {
+    fn report(&self, outcome: Outcome, detail: String, pid: Option<u32>) -> WakeRecord {
+        WakeRecord { time: Local::now(), outcome, detail: Some(detail), pid, ..self.origin.clone() }
+    }
+}
+
+// Keeps the programs of rules with a `supervise` table running, keyed by rule name.
+#[derive(Default)]
+pub struct Supervisor {
+    programs: HashMap<String, Supervised>,
+    last_poll: Option<Instant>,
+}
+
+impl Supervisor {
+    // Starts supervising a program that was just launched for `rule`, replacing what was supervised for it.
+    pub fn watch(&mut self, rule: &str, action: Action, config: SuperviseConfig, process: Process,
+                 origin: WakeRecord) {
+        let root = Pid::from_u32(process.id());
+        info!(rule, program = action.run_path.as_str(), pid = process.id(); "Supervising {}", action.run_path);
+        self.programs.insert(rule.to_string(), Supervised {
+            action, config, process: Some(process), exit: None, tree: HashSet::from([root]),
+            started_at: Instant::now(), restarts: 0, restart_at: None, origin,
+        });
+    }
+
+    // After a reload: programs whose rule is gone, no longer supervised or starts something else are let go,
+    // the others take the new settings.
+    pub fn update(&mut self, rules: &[Rule], default: &Action) {
+        self.programs.retain(|name, supervised| {
+            let rule = rules.iter().find(|rule| rule.name == *name);
+            let config = rule.filter(|rule| rule.action(default) == supervised.action
+                                         || rule.fallback.as_ref() == Some(&supervised.action))
+                .and_then(|rule| rule.supervise.clone());
+            match config {
+                Some(config) => {
+                    supervised.config = config;
+                    true
+                },
+                None => {
+                    info!(rule = name.as_str(); "No longer supervising {}", supervised.action.run_path);
+                    false
+                },
+            }
+        });
+    }
+
+    // Stops supervising `action`, which is about to be stopped on purpose.
+    pub fn release(&mut self, action: &Action) {
+        self.programs.retain(|rule, supervised| {
+            let keep = supervised.action.run_path != action.run_path;
+            if !keep {
+                info!(rule = rule.as_str(); "No longer supervising {}, it is being stopped", supervised.action.run_path);
+            }
+            keep
+        });
+    }
+
+    // Collects exits and restarts programs whose backoff has passed. `launch` starts an action for a rule;
+    // the returned records are for the wake history. During a dry run programs are not restarted.
+    pub fn poll(&mut self, launched: &HashSet<u32>, dry_run: bool,
+                mut launch: impl FnMut(&str, &Action) -> Result<Process, i32>) -> Vec<WakeRecord> {
+        if self.programs.is_empty() || self.last_poll.is_some_and(|last_poll| last_poll.elapsed() < POLL_INTERVAL) {
+            return Vec::new();
+        }
+        self.last_poll = Some(Instant::now());
+        let mut system = System::new();
+        system.refresh_processes();
+        let mut records = Vec::new();
+        let mut finished = Vec::new();
+        for (rule, supervised) in self.programs.iter_mut() {
+            match supervised.restart_at {
+                Some(restart_at) if restart_at <= Instant::now() => {
+                    if restart(rule, supervised, launched, dry_run, &mut launch, &mut records) {
+                        finished.push(rule.clone());
+                    }
+                },
+                Some(_) => {},
+                None => {
+                    if check(rule, supervised, &system, &mut records) {
+                        finished.push(rule.clone());
+                    }
+                },
+            }
+        }
+        for rule in finished {
+            self.programs.remove(&rule);
+        }
+        records
+    }
+}
+
+// Collects the exit of the started process and follows its children. Returns true when supervision ends.
+fn check(rule: &str, supervised: &mut Supervised, system: &System, records: &mut Vec<WakeRecord>) -> bool {
+    let processes = system.processes();
+    if let Some(process) = supervised.process.as_mut() {
+        let root = Pid::from_u32(process.id());
+        // Gone from the process list without an exit code: it could not be opened.
+        let exit = process.try_exit().or_else(|| (!processes.contains_key(&root)).then_some(Exit::Unknown));
+        if let Some(exit) = exit {
+            supervised.tree.remove(&root);
+            supervised.process = None;
+            supervised.exit = Some(exit);
+        }
+    }
+    // Processes started by the program keep it alive, even after their parent has exited.
+    supervised.tree.retain(|pid| processes.contains_key(pid));
+    loop {
+        let children: Vec<Pid> = processes.iter()
+            .filter(|(pid, process)| !supervised.tree.contains(pid)
+                && process.parent().is_some_and(|parent| supervised.tree.contains(&parent)))
+            .map(|(pid, _)| *pid)
+            .collect();
+        if children.is_empty() {
+            break;
+        }
+        supervised.tree.extend(children);
+    }
+    if supervised.process.is_some() || !supervised.tree.is_empty() {
+        return false;
+    }
+
+    let exit = supervised.exit.unwrap_or(Exit::Unknown);
+    let program = supervised.action.run_path.as_str();
+    if supervised.config.restart == Restart::OnFailure && !exit.failed() {
+        info!(rule, program; "{} {}, no longer supervising it", program, exit);
+        return true;
+    }
+    // A long enough run means the earlier crashes are over with.
+    let ran = supervised.started_at.elapsed();
+    if ran >= Duration::from_secs(supervised.config.stable_secs) {
+        supervised.restarts = 0;
+    }
+    if supervised.restarts >= supervised.config.max_restarts {
+        error!(rule, program; "{} {} after {}s, giving up after {} restarts", program, exit, ran.as_secs(),
+               supervised.restarts);
+        records.push(supervised.report(Outcome::Crashed,
+                                         format!("{}; gave up after {} restarts", exit, supervised.restarts), None));
+        return true;
+    }
+    let backoff = supervised.config.backoff(supervised.restarts);
+    warn!(rule, program; "{} {} after {}s, restarting in {}s", program, exit, ran.as_secs(), backoff.as_secs());
+    records.push(supervised.report(Outcome::Crashed, format!("{}; restarting in {}s", exit, backoff.as_secs()), None));
+    supervised.restart_at = Some(Instant::now() + backoff);
+    false
+}
+
+// Restarts the program once its backoff has passed. Returns true when supervision ends.
+fn restart(rule: &str, supervised: &mut Supervised, launched: &HashSet<u32>, dry_run: bool,
+           launch: &mut impl FnMut(&str, &Action) -> Result<Process, i32>, records: &mut Vec<WakeRecord>) -> bool {
+    let program = supervised.action.run_path.as_str();
+    // Started by hand or by another wake in the meantime.
+    if super::is_program_running(&supervised.action, Some(launched)) {
+        info!(rule, program; "{} is running again, no longer supervising it", supervised.action.program);
+        return true;
+    }
+    supervised.restarts += 1;
+    let attempt = supervised.restarts;
+    // Nothing is left to follow once the restart is only recorded.
+    if dry_run {
+        let plan = super::launch_plan(&supervised.action);
+        info!(rule, program; "Dry run, would restart {}; no longer supervising it", plan);
+        records.push(supervised.report(Outcome::DryRun, format!("restart {}: would start {}", attempt, plan), None));
+        return true;
+    }
+    match launch(rule, &supervised.action) {
+        Ok(process) => {
+            let pid = process.id();
+            records.push(supervised.report(Outcome::Restarted, format!("restart {}", attempt), Some(pid)));
+            supervised.tree = HashSet::from([Pid::from_u32(pid)]);
+            supervised.process = Some(process);
+            supervised.exit = None;
+            supervised.started_at = Instant::now();
+            supervised.restart_at = None;
+            false
+        },
+        Err(e) if attempt >= supervised.config.max_restarts => {
+            error!(rule, program; "Restart {} of {} failed, giving up", attempt, program);
+            records.push(supervised.report(Outcome::Failed, format!("restart {}: error {}; gave up", attempt, e), None));
+            true
+        },
+        Err(e) => {
+            let backoff = supervised.config.backoff(attempt);
+            records.push(supervised.report(Outcome::Failed,
+                                             format!("restart {}: error {}; retrying in {}s", attempt, e, backoff.as_secs()),
+                                             None));
+            supervised.restart_at = Some(Instant::now() + backoff);
+            false
+        },
+    }
+}
@@ -0,0 +1,438 @@
+use std::{fmt, net::{IpAddr, SocketAddr}, str::FromStr};
+use pnet::packet::{
+    ethernet::{EtherTypes, EthernetPacket},
+    ip::IpNextHeaderProtocols,
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    udp::UdpPacket,
+    Packet,
+};
+use pnet::util::MacAddr;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+// Size of the magic packet payload: 6 bytes of 0xFF followed by 16 copies of the target MAC.
+pub const MAGIC_PACKET_LEN: usize = 6 + 16 * 6;
+// The interface of records for launches requested through the control API rather than by a wake packet.
+pub const API_SOURCE: &str = "api";
+
+// A detected magic packet together with everything we know about where it came from.
+#[derive(Debug, Clone)]
+pub struct WakeEvent {
+    pub received_at: DateTime<Local>,
+    pub interface: String,
+    pub target_mac: MacAddr,
+    pub source_mac: MacAddr,
+    pub source_ip: Option<IpAddr>,
+    pub source_port: Option<u16>,
+    pub encapsulation: Encapsulation,
+    // Bytes following the magic packet: a SecureOn password or an authentication trailer.
+    pub trailer: Vec<u8>,
+}
+
+// How the magic packet was carried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encapsulation {
+    // UDP datagram, with its destination port.
+    Udp(u16),
+    // Another IP protocol.
+    Ip,
+    // Raw ethernet payload, with the EtherType (0x0842 for Wake-on-LAN).
+    Ethernet(u16),
+}
+
+impl fmt::Display for Encapsulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encapsulation::Udp(port) => write!(f, "UDP port {}", port),
+            Encapsulation::Ip => write!(f, "IP"),
+            Encapsulation::Ethernet(ethertype) => write!(f, "EtherType 0x{:04x}", ethertype),
+        }
+    }
+}
+
+impl WakeEvent {
+    // Returns None when the frame does not carry a magic packet.
+    pub fn from_frame(interface: &str, frame: &[u8]) -> Option<WakeEvent> {
+        // Cheap check on the raw frame before decoding any headers.
+        find_magic_packet(frame)?;
+        let ethernet = EthernetPacket::new(frame)?;
+        let ethertype = ethernet.get_ethertype();
+        let (source_ip, udp) = match ethertype {
+            EtherTypes::Ipv4 => match Ipv4Packet::new(ethernet.payload()) {
+                Some(ip) if ip.get_next_level_protocol() == IpNextHeaderProtocols::Udp =>
+                    (Some(IpAddr::V4(ip.get_source())), UdpPacket::owned(ip.payload().to_vec())),
+                Some(ip) => (Some(IpAddr::V4(ip.get_source())), None),
+                None => (None, None),
+            },
+            EtherTypes::Ipv6 => match Ipv6Packet::new(ethernet.payload()) {
+                Some(ip) if ip.get_next_header() == IpNextHeaderProtocols::Udp =>
+                    (Some(IpAddr::V6(ip.get_source())), UdpPacket::owned(ip.payload().to_vec())),
+                Some(ip) => (Some(IpAddr::V6(ip.get_source())), None),
+                None => (None, None),
+            },
+            // EtherType 0x0842 and anything else: the magic packet is sent as raw ethernet payload.
+            _ => (None, None),
+        };
+        let (source_port, encapsulation, payload) = match (&udp, source_ip) {
+            (Some(udp), _) => (Some(udp.get_source()), Encapsulation::Udp(udp.get_destination()), Some(udp.payload().to_vec())),
+            (None, Some(_)) => (None, Encapsulation::Ip, None),
+            (None, None) => (None, Encapsulation::Ethernet(ethertype.0), None),
+        };
+        // Prefer the UDP payload so that ethernet padding never ends up in the trailer.
+        let payload = payload.unwrap_or_else(|| ethernet.payload().to_vec());
+        let (payload, wol_start) = match find_magic_packet(&payload) {
+            Some(start) => (payload.as_slice(), start),
+            None => (frame, find_magic_packet(frame)?),
+        };
+        let mac = &payload[wol_start + 6..wol_start + 12];
+
+        Some(WakeEvent {
+            received_at: Local::now(),
+            interface: interface.to_string(),
+            target_mac: MacAddr::new(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]),
+            source_mac: ethernet.get_source(),
+            source_ip,
+            source_port,
+            encapsulation,
+            trailer: payload[wol_start + MAGIC_PACKET_LEN..].to_vec(),
+        })
+    }
+
+    // A 4 or 6 byte trailer is a SecureOn password, shown like a MAC address.
+    pub fn password(&self) -> Option<String> {
+        match self.trailer.len() {
+            4 | 6 => Some(self.trailer.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join("-")),
+            _ => None,
+        }
+    }
+
+    // The sender's IP address and port if known, its MAC otherwise.
+    pub fn source(&self) -> String {
+        match (self.source_ip, self.source_port) {
+            (Some(ip), Some(port)) => SocketAddr::new(ip, port).to_string(),
+            (Some(ip), None) => ip.to_string(),
+            _ => self.source_mac.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for WakeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "target {} from {}", self.target_mac, self.source_mac)?;
+        match (self.source_ip, self.source_port) {
+            (Some(ip), Some(port)) => write!(f, " ({})", SocketAddr::new(ip, port))?,
+            (Some(ip), None) => write!(f, " ({})", ip)?,
+            _ => {}
+        }
+        write!(f, " on {}", self.interface)
+    }
+}
+
+// What the service did with a wake event.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Rejected,
+    OutOfSchedule,
+    NoMatch,
+    AlreadyRunning,
+    Launched,
+    Failed,
+    // Matched, but the service runs with --dry-run and only recorded what it would start.
+    DryRun,
+    // A supervised program exited abnormally.
+    Crashed,
+    // A supervised program was started again after a crash.
+    Restarted,
+    // A toggle or stop rule asked the running program to exit.
+    Stopped,
+    // A stop rule found nothing to stop.
+    NotRunning,
+    // Nobody was logged on; the launch waits for a user session.
+    Queued,
+    // Nobody logged on before the queued launch expired.
+    Expired,
+    // The queued launch was cancelled through the API.
+    Cancelled,
+    // The started program did not pass its readiness probes in time.
+    NotReady,
+}
+
+impl Outcome {
+    pub const ALL: [&'static str; 15] = ["rejected", "out_of_schedule", "no_match", "already_running", "launched", "failed",
+                                         "dry_run", "crashed", "restarted", "stopped", "not_running", "queued",
+                                         "expired", "cancelled", "not_ready"];
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Outcome::Rejected => "rejected",
+            Outcome::OutOfSchedule => "out_of_schedule",
+            Outcome::NoMatch => "no_match",
+            Outcome::AlreadyRunning => "already_running",
+            Outcome::Launched => "launched",
+            Outcome::Failed => "failed",
+            Outcome::DryRun => "dry_run",
+            Outcome::Crashed => "crashed",
+            Outcome::Restarted => "restarted",
+            Outcome::Stopped => "stopped",
+            Outcome::NotRunning => "not_running",
+            Outcome::Queued => "queued",
+            Outcome::Expired => "expired",
+            Outcome::Cancelled => "cancelled",
+            Outcome::NotReady => "not_ready",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Outcome, String> {
+        match s {
+            "rejected" => Ok(Outcome::Rejected),
+            "out_of_schedule" => Ok(Outcome::OutOfSchedule),
+            "no_match" => Ok(Outcome::NoMatch),
+            "already_running" => Ok(Outcome::AlreadyRunning),
+            "launched" => Ok(Outcome::Launched),
+            "failed" => Ok(Outcome::Failed),
+            "dry_run" => Ok(Outcome::DryRun),
+            "crashed" => Ok(Outcome::Crashed),
+            "restarted" => Ok(Outcome::Restarted),
+            "stopped" => Ok(Outcome::Stopped),
+            "not_running" => Ok(Outcome::NotRunning),
+            "queued" => Ok(Outcome::Queued),
+            "expired" => Ok(Outcome::Expired),
+            "cancelled" => Ok(Outcome::Cancelled),
+            "not_ready" => Ok(Outcome::NotReady),
+            _ => Err(format!("unknown outcome {}, expected one of {}", s, Outcome::ALL.join(", "))),
+        }
+    }
+}
+
+// A wake event together with its outcome, as reported by the control API and kept in the history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WakeRecord {
+    pub time: DateTime<Local>,
+    pub interface: String,
+    pub target_mac: MacAddr,
+    pub source_mac: MacAddr,
+    pub source_ip: Option<IpAddr>,
+    pub source_port: Option<u16>,
+    pub rule: Option<String>,
+    // The executable that was (or would have been) started.
+    pub action: Option<String>,
+    pub outcome: Outcome,
+    // Rejection reason, launch error or how a supervised program exited.
+    pub detail: Option<String>,
+    pub pid: Option<u32>,
+}
+
+impl WakeRecord {
+    pub fn new(event: &WakeEvent, rule: Option<&str>, outcome: Outcome) -> WakeRecord {
+        WakeRecord {
+            time: event.received_at,
+            interface: event.interface.clone(),
+            target_mac: event.target_mac,
+            source_mac: event.source_mac,
+            source_ip: event.source_ip,
+            source_port: event.source_port,
+            rule: rule.map(String::from),
+            action: None,
+            outcome,
+            detail: None,
+            pid: None,
+        }
+    }
+
+    // A record for a launch triggered through the control API: there is no packet, so no sender.
+    pub fn api(rule: &str, target_mac: Option<MacAddr>, action: &str, outcome: Outcome) -> WakeRecord {
+        WakeRecord {
+            time: Local::now(),
+            interface: API_SOURCE.to_string(),
+            target_mac: target_mac.unwrap_or(MacAddr::zero()),
+            source_mac: MacAddr::zero(),
+            source_ip: None,
+            source_port: None,
+            rule: Some(rule.to_string()),
+            action: Some(action.to_string()),
+            outcome,
+            detail: None,
+            pid: None,
+        }
+    }
+
+    // The sender's IP address and port if known, its MAC otherwise, or `api`.
+    pub fn source(&self) -> String {
+        match (self.source_ip, self.source_port) {
+            _ if self.interface == API_SOURCE => API_SOURCE.to_string(),
+            (Some(ip), Some(port)) => SocketAddr::new(ip, port).to_string(),
+            (Some(ip), None) => ip.to_string(),
+            _ => self.source_mac.to_string(),
+        }
+    }
+}
+
+// Builds a plain magic packet for the given target.
+pub fn magic_packet(target_mac: MacAddr) -> Vec<u8> {
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&target_mac.octets());
+    }
+    packet
+}
+
+// Returns the offset of the first magic packet in the buffer, like NIC firmware
+// which accepts the sequence anywhere in the frame.
+pub fn find_magic_packet(packet: &[u8]) -> Option<usize> {
+    if packet.len() < MAGIC_PACKET_LEN {
+        return None;
+    }
+    (0..=packet.len() - MAGIC_PACKET_LEN)
+        .find(|&start| packet[start] == 0xff && is_wol_packet(&packet[start..start + MAGIC_PACKET_LEN]))
+}
+
+pub fn is_wol_packet(packet: &[u8]) -> bool {
+    // Minimum length for WOL payload
+    if packet.len() < MAGIC_PACKET_LEN {
+        return false;
+    }
+
+    let wol_start = packet.len() - MAGIC_PACKET_LEN;
+
+    // Check for 6 bytes of 0xFF
+    if packet[wol_start..wol_start + 6] != [0xff, 0xff, 0xff, 0xff, 0xff, 0xff] {
+        return false;
+    }
+
+    // Get the repeated MAC address from the packet
+    let mac = &packet[wol_start + 6..wol_start + 12];
+
+    // Check for 16 repetitions of the MAC address
+    for i in 0..16 {
+        if packet[wol_start + 6 + i * 6..wol_start + 6 + (i + 1) * 6] != *mac {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const TARGET: MacAddr = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+    const SENDER: MacAddr = MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb);
+
+    fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&SENDER.octets());
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn udp(port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = 40000u16.to_be_bytes().to_vec();
+        datagram.extend_from_slice(&port.to_be_bytes());
+        datagram.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    fn ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0]);
+        packet.extend_from_slice(&[192, 168, 1, 20]);
+        packet.extend_from_slice(&[192, 168, 1, 255]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn ipv6(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[17, 64]);
+        packet.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        packet.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn parses_raw_ethertype_0842() {
+        let event = WakeEvent::from_frame("eth0", &ethernet(0x0842, &magic_packet(TARGET))).unwrap();
+        assert_eq!(event.target_mac, TARGET);
+        assert_eq!(event.source_mac, SENDER);
+        assert_eq!(event.encapsulation, Encapsulation::Ethernet(0x0842));
+        assert_eq!((event.source_ip, event.source_port), (None, None));
+        assert!(event.trailer.is_empty());
+        assert_eq!(event.source(), SENDER.to_string());
+    }
+
+    #[test]
+    fn parses_udp_ports_7_and_9() {
+        for port in [7, 9] {
+            let event = WakeEvent::from_frame("eth0", &ethernet(0x0800, &ipv4(17, &udp(port, &magic_packet(TARGET))))).unwrap();
+            assert_eq!(event.target_mac, TARGET);
+            assert_eq!(event.encapsulation, Encapsulation::Udp(port));
+            assert_eq!(event.source_ip, Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))));
+            assert_eq!(event.source_port, Some(40000));
+            assert_eq!(event.source(), "192.168.1.20:40000");
+        }
+    }
+
+    #[test]
+    fn parses_udp_over_ipv6() {
+        let event = WakeEvent::from_frame("eth0", &ethernet(0x86dd, &ipv6(&udp(9, &magic_packet(TARGET))))).unwrap();
+        assert_eq!(event.target_mac, TARGET);
+        assert_eq!(event.encapsulation, Encapsulation::Udp(9));
+        assert_eq!(event.source_ip, Some(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn parses_other_ip_protocols() {
+        let event = WakeEvent::from_frame("eth0", &ethernet(0x0800, &ipv4(6, &magic_packet(TARGET)))).unwrap();
+        assert_eq!(event.encapsulation, Encapsulation::Ip);
+        assert_eq!(event.source_port, None);
+    }
+
+    #[test]
+    fn finds_the_magic_packet_after_a_leading_offset() {
+        let mut payload = vec![0x01; 10];
+        payload.extend_from_slice(&magic_packet(TARGET));
+        payload.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(find_magic_packet(&payload), Some(10));
+        let event = WakeEvent::from_frame("eth0", &ethernet(0x0800, &ipv4(17, &udp(9, &payload)))).unwrap();
+        assert_eq!(event.target_mac, TARGET);
+        assert_eq!(event.trailer, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(event.password().as_deref(), Some("de-ad-be-ef"));
+    }
+
+    #[test]
+    fn ignores_truncated_frames() {
+        let frame = ethernet(0x0842, &magic_packet(TARGET));
+        assert!(WakeEvent::from_frame("eth0", &frame[..frame.len() - 1]).is_none());
+        assert!(WakeEvent::from_frame("eth0", &frame[..10]).is_none());
+        assert!(WakeEvent::from_frame("eth0", &[]).is_none());
+        assert!(!is_wol_packet(&magic_packet(TARGET)[..MAGIC_PACKET_LEN - 1]));
+        assert_eq!(find_magic_packet(&magic_packet(TARGET)[1..]), None);
+    }
+
+    #[test]
+    fn ignores_repetitions_that_do_not_match() {
+        let mut packet = magic_packet(TARGET);
+        packet[6 + 15 * 6] ^= 0x01;
+        assert!(!is_wol_packet(&packet));
+        assert_eq!(find_magic_packet(&packet), None);
+        assert!(WakeEvent::from_frame("eth0", &ethernet(0x0842, &packet)).is_none());
+        assert!(WakeEvent::from_frame("eth0", &ethernet(0x0800, &ipv4(17, &udp(9, &packet)))).is_none());
+    }
+}
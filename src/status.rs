@@ -0,0 +1,273 @@
+#[cfg(windows)]
+use std::ffi::OsString;
+use serde::{Deserialize, Serialize};
+#[cfg(windows)]
+use windows_service::{
+    service::{ServiceAccess, ServiceState},
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::ERROR_SERVICE_DOES_NOT_EXIST;
+use pnet::datalink::NetworkInterface;
+use crate::config::Config;
+use crate::service::{self, api, history, queue::QueuedLaunch, rules::{self, Action}};
+use crate::service::state::{Activity, ProgramStatus};
+use crate::service::wake_event::WakeRecord;
+
+// What the `status` subcommand reports, either from the running service or from static checks.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub installed: bool,
+    pub service_state: Option<String>,
+    // "service" if the running instance answered through its API, "static" otherwise.
+    pub source: &'static str,
+    pub activity: Option<Activity>,
+    pub config_path: Option<String>,
+    pub interfaces: Vec<InterfaceSummary>,
+    pub programs: Vec<ProgramStatus>,
+    // Launches waiting for somebody to log on; only the running service knows them.
+    pub queue: Vec<QueuedLaunch>,
+    pub last_event: Option<WakeRecord>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InterfaceSummary {
+    pub name: String,
+    pub description: String,
+    pub ips: Vec<String>,
+}
+
+impl From<&NetworkInterface> for InterfaceSummary {
+    fn from(interface: &NetworkInterface) -> InterfaceSummary {
+        InterfaceSummary {
+            name: interface.name.clone(),
+            description: interface.description.clone(),
+            ips: interface.ips.iter().map(|ip| ip.to_string()).collect(),
+        }
+    }
+}
+
+// The part of the API's `/status` response we report.
+#[derive(Deserialize)]
+struct ApiStatus {
+    activity: Activity,
+    config_path: Option<String>,
+    programs: Vec<ProgramStatus>,
+    // Missing from services older than the queue.
+    #[serde(default)]
+    queue: Vec<QueuedLaunch>,
+    last_event: Option<WakeRecord>,
+}
+
+// The installed service as registered with the SCM or systemd.
+pub struct Installation {
+    // "running", "stopped", ...
+    pub state: String,
+    // Launch arguments: program, run path, host ip, log level and the optional config file.
+    pub arguments: Vec<String>,
+}
+
+// `config_path` overrides the installed config file; `defaults` (program, run path, host ip)
+// stand in for the launch arguments when the service is not installed.
+pub fn collect(config_path: Option<&str>, defaults: [&str; 3]) -> StatusReport {
+    let mut warnings = Vec::new();
+    let installation = match query_installation() {
+        Ok(installation) => installation,
+        Err(e) => {
+            warnings.push(format!("Cannot query the service manager: {}", e));
+            None
+        }
+    };
+    let argument = |index: usize, default: &str| installation.as_ref()
+        .and_then(|installation| installation.arguments.get(index).cloned())
+        .unwrap_or_else(|| default.to_string());
+    let default_action = Action::new(&argument(0, defaults[0]), &argument(1, defaults[1]));
+    let host_ip = argument(2, defaults[2]);
+    let config_path = config_path.map(String::from)
+        .or_else(|| installation.as_ref().and_then(|installation| installation.arguments.get(4).cloned()));
+    let config = match &config_path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            warnings.push(e);
+            Config::default()
+        }),
+        None => Config::default(),
+    };
+    let default_action = config.default_action(&default_action);
+    let host_ip = config.host_ip(&host_ip);
+
+    let mut report = StatusReport {
+        installed: installation.is_some(),
+        service_state: installation.as_ref().map(|installation| installation.state.clone()),
+        source: "static",
+        activity: None,
+        config_path,
+        interfaces: Vec::new(),
+        programs: Vec::new(),
+        queue: Vec::new(),
+        last_event: None,
+        warnings,
+    };
+
+    let running = installation.as_ref().is_some_and(|installation| installation.state == "running");
+    if let (true, Some(api_config)) = (running, &config.api) {
+        let answer = api::request::<ApiStatus>(api_config, "GET", "/status").and_then(|status| {
+            api::request::<Vec<NetworkInterface>>(api_config, "GET", "/interfaces").map(|interfaces| (status, interfaces))
+        });
+        match answer {
+            Ok((status, interfaces)) => {
+                report.source = "service";
+                report.activity = Some(status.activity);
+                report.config_path = status.config_path;
+                report.interfaces = interfaces.iter().map(InterfaceSummary::from).collect();
+                report.programs = status.programs;
+                report.queue = status.queue;
+                report.last_event = status.last_event;
+                return report;
+            },
+            Err(e) => report.warnings.push(format!("The running service did not answer: {}", e)),
+        }
+    }
+
+    // Static checks: what the service would see if it were running now.
+    match service::select_interface(&host_ip) {
+        Some(interface) => report.interfaces.push(InterfaceSummary::from(&interface)),
+        None => report.warnings.push(format!("No interface has an IP address starting with {}", host_ip)),
+    }
+    report.programs = rules::all_actions(&config.effective_rules(), &default_action).into_iter()
+        .map(|action| ProgramStatus { running: service::is_program_running(&action, None), program: action.program })
+        .collect();
+    if config.history.enabled {
+        let path = config.history.path();
+        if path.exists() {
+            match history::read(&path) {
+                Ok(mut records) => report.last_event = records.pop(),
+                Err(e) => report.warnings.push(e),
+            }
+        }
+    }
+    report
+}
+
+#[cfg(unix)]
+pub use crate::systemd::query_installation;
+
+#[cfg(windows)]
+pub fn query_installation() -> Result<Option<Installation>, String> {
+    query_scm().map_err(|e| e.to_string())
+}
+
+#[cfg(windows)]
+fn query_scm() -> windows_service::Result<Option<Installation>> {
+    let service_manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = match service_manager.open_service(service::SERVICE_NAME,
+                                                     ServiceAccess::QUERY_STATUS | ServiceAccess::QUERY_CONFIG) {
+        Ok(service) => service,
+        Err(windows_service::Error::Winapi(e)) if e.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST as i32) => {
+            return Ok(None);
+        },
+        Err(e) => return Err(e),
+    };
+    let state = service.query_status()?.current_state;
+    let command_line = service.query_config()?.executable_path.into_os_string();
+    // Skip the executable itself.
+    let arguments = split_command_line(&command_line).into_iter().skip(1).collect();
+    Ok(Some(Installation { state: state_name(state).to_string(), arguments }))
+}
+
+// Splits a command line the way CommandLineToArgvW does, undoing the quoting applied at install.
+#[cfg(windows)]
+fn split_command_line(command_line: &OsString) -> Vec<String> {
+    let command_line = command_line.to_string_lossy();
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut quoted = false;
+    let mut backslashes = 0;
+    for c in command_line.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                in_argument = true;
+                continue;
+            },
+            '"' => {
+                current.push_str(&"\\".repeat(backslashes / 2));
+                if backslashes % 2 == 1 {
+                    current.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+                in_argument = true;
+            },
+            ' ' | '\t' if !quoted => {
+                current.push_str(&"\\".repeat(backslashes));
+                if in_argument {
+                    arguments.push(std::mem::take(&mut current));
+                    in_argument = false;
+                }
+            },
+            c => {
+                current.push_str(&"\\".repeat(backslashes));
+                current.push(c);
+                in_argument = true;
+            },
+        }
+        backslashes = 0;
+    }
+    current.push_str(&"\\".repeat(backslashes));
+    if in_argument {
+        arguments.push(current);
+    }
+    arguments
+}
+
+#[cfg(windows)]
+fn state_name(state: ServiceState) -> &'static str {
+    match state {
+        ServiceState::Stopped => "stopped",
+        ServiceState::StartPending => "starting",
+        ServiceState::StopPending => "stopping",
+        ServiceState::Running => "running",
+        ServiceState::ContinuePending => "resuming",
+        ServiceState::PausePending => "pausing",
+        ServiceState::Paused => "paused",
+    }
+}
+
+pub fn print(report: &StatusReport) {
+    match &report.service_state {
+        Some(state) => println!("Service:    installed, {}", state),
+        None => println!("Service:    not installed"),
+    }
+    println!("Details:    {}", match report.source {
+        "service" => "reported by the running service",
+        _ => "static checks",
+    });
+    if let Some(activity) = report.activity {
+        println!("Activity:   {}", activity);
+    }
+    println!("Config:     {}", report.config_path.as_deref().unwrap_or("none"));
+    for interface in &report.interfaces {
+        println!("Interface:  {} ({}) {}", interface.name, interface.description, interface.ips.join(", "));
+    }
+    for program in &report.programs {
+        println!("Program:    {} {}", program.program, if program.running { "running" } else { "not running" });
+    }
+    for launch in &report.queue {
+        println!("Queued:     {} for rule {} since {}, until {}", launch.action, launch.rule,
+                 launch.queued_at.format("%Y-%m-%d %H:%M:%S"), launch.expires_at.format("%Y-%m-%d %H:%M:%S"));
+    }
+    match &report.last_event {
+        Some(event) => println!("Last wake:  {} target {} from {}: {}{}",
+                                event.time.format("%Y-%m-%d %H:%M:%S"),
+                                event.target_mac,
+                                event.source(),
+                                event.outcome,
+                                event.pid.map_or(String::new(), |pid| format!(" (pid {})", pid))),
+        None => println!("Last wake:  none"),
+    }
+    for warning in &report.warnings {
+        println!("Warning:    {}", warning);
+    }
+}
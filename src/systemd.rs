@@ -0,0 +1,423 @@
+use std::{collections::BTreeSet, env, fs, path::Path, process::Command};
+use log::warn;
+use crate::config::Config;
+use crate::service::{limits::Namespace, rules::{Action, Rule}};
+use crate::status::Installation;
+
+pub const UNIT_NAME: &str = "wolstart.service";
+const DEFAULT_UNIT_DIR: &str = "/etc/systemd/system";
+
+// The install and uninstall options that only apply to systemd.
+pub fn install_args(command: clap::Command) -> clap::Command {
+    uninstall_args(command)
+        .arg(
+            clap::arg!(--"user" <USER>)
+                .help("Run the service as USER with CAP_NET_RAW and what the rules need instead of as root")
+                .required(false),
+        )
+        .arg(
+            clap::arg!(--"enable")
+                .help("Enable the unit so it starts at boot"),
+        )
+        .arg(
+            clap::arg!(--"start")
+                .help("Start the unit right away"),
+        )
+}
+
+// The global --dry-run prints the unit and the systemctl commands instead of applying them.
+pub fn uninstall_args(command: clap::Command) -> clap::Command {
+    command
+        .arg(
+            clap::arg!(--"unit-dir" <DIR>)
+                .help("Directory for the unit file")
+                .default_value(DEFAULT_UNIT_DIR),
+        )
+}
+
+// `arguments` are the service's launch arguments: program, run path, host ip, log level and the optional config file.
+// The unit allows what the rules of `config` need.
+pub fn install(arguments: &[String], config: &Config, matches: &clap::ArgMatches) -> Result<(), String> {
+    let unit_path = Path::new(matches.get_one::<String>("unit-dir").unwrap()).join(UNIT_NAME);
+    let dry_run = matches.get_flag("dry-run");
+    if unit_path.exists() && !dry_run {
+        return Err(format!("{} already exists, uninstall first", unit_path.display()));
+    }
+    let executable = env::current_exe().map_err(|e| format!("Cannot locate the executable: {}", e))?;
+    let user = matches.get_one::<String>("user").map(String::as_str);
+    if user.is_none() {
+        warn!("The service and the programs it starts run as root; pass --user to run them unprivileged");
+    }
+    let default_action = config.default_action(&Action::new(&arguments[0], &arguments[1]));
+    let unit = unit(&executable, arguments, user, &Needs::of_rules(&config.effective_rules(), &default_action, user));
+
+    let mut commands = vec![vec!["daemon-reload"]];
+    match (matches.get_flag("enable"), matches.get_flag("start")) {
+        (true, true) => commands.push(vec!["enable", "--now", UNIT_NAME]),
+        (true, false) => commands.push(vec!["enable", UNIT_NAME]),
+        (false, true) => commands.push(vec!["start", UNIT_NAME]),
+        (false, false) => {},
+    }
+    if dry_run {
+        println!("Would write {}:\n{}", unit_path.display(), unit);
+        for command in &commands {
+            println!("Would run: systemctl {}", command.join(" "));
+        }
+        return Ok(());
+    }
+
+    if let Some(dir) = unit_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&unit_path, unit).map_err(|e| format!("Failed to write {}: {}", unit_path.display(), e))?;
+    println!("Wrote {}", unit_path.display());
+    // A unit directory systemd does not watch is fine for testing, so only warn when it cannot reload.
+    if let Err(e) = systemctl(commands[0].as_slice()) {
+        warn!("{}", e);
+    }
+    for command in &commands[1..] {
+        systemctl(command)?;
+    }
+    Ok(())
+}
+
+pub fn uninstall(matches: &clap::ArgMatches) -> Result<(), String> {
+    let unit_dir = matches.get_one::<String>("unit-dir").unwrap();
+    let unit_path = Path::new(unit_dir).join(UNIT_NAME);
+    if !unit_path.exists() {
+        return Err(format!("{} is not installed in {}", UNIT_NAME, unit_dir));
+    }
+    if matches.get_flag("dry-run") {
+        println!("Would run: systemctl disable --now {}", UNIT_NAME);
+        println!("Would remove {}", unit_path.display());
+        println!("Would run: systemctl daemon-reload");
+        return Ok(());
+    }
+
+    // The unit may never have been enabled or started.
+    if let Err(e) = systemctl(&["disable", "--now", UNIT_NAME]) {
+        warn!("{}", e);
+    }
+    fs::remove_file(&unit_path).map_err(|e| format!("Failed to remove {}: {}", unit_path.display(), e))?;
+    if let Err(e) = systemctl(&["daemon-reload"]) {
+        warn!("{}", e);
+    }
+    println!("{} is removed.", UNIT_NAME);
+    Ok(())
+}
+
+// systemd sends SIGHUP for `systemctl reload`, see ExecReload in the unit.
+pub fn reload() -> Result<(), String> {
+    systemctl(&["reload", UNIT_NAME])
+}
+
+pub fn query_installation() -> Result<Option<Installation>, String> {
+    let Some((state, unit)) = installed_unit()? else {
+        return Ok(None);
+    };
+    // Skip the executable itself.
+    let arguments = unit.lines()
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .map(|line| split_exec_line(line).into_iter().skip(1).collect())
+        .unwrap_or_default();
+    let state = match state.as_str() {
+        "active" => "running",
+        "activating" => "starting",
+        "deactivating" => "stopping",
+        "inactive" => "stopped",
+        other => other,
+    };
+    Ok(Some(Installation { state: state.to_string(), arguments }))
+}
+
+// The sandbox of the installed unit, or None if the service is not installed.
+pub fn installed_sandbox() -> Result<Option<Sandbox>, String> {
+    Ok(installed_unit()?.map(|(_, unit)| Sandbox::parse(&unit)))
+}
+
+// The active state and the contents of the installed unit.
+fn installed_unit() -> Result<Option<(String, String)>, String> {
+    let output = Command::new("systemctl")
+        .args(["show", UNIT_NAME, "--property=LoadState,ActiveState,FragmentPath"])
+        .output()
+        .map_err(|e| format!("Failed to run systemctl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("systemctl show failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let properties = String::from_utf8_lossy(&output.stdout);
+    let property = |name: &str| properties.lines()
+        .find_map(|line| line.strip_prefix(name).and_then(|line| line.strip_prefix('=')))
+        .unwrap_or("");
+    if property("LoadState") == "not-found" {
+        return Ok(None);
+    }
+    let unit_path = property("FragmentPath");
+    let unit = fs::read_to_string(unit_path).map_err(|e| format!("Failed to read {}: {}", unit_path, e))?;
+    Ok(Some((property("ActiveState").to_string(), unit)))
+}
+
+// What starting the programs needs from the unit: the namespace types the limits ask for, and the
+// capabilities beyond CAP_NET_RAW the service needs when it does not run as root.
+#[derive(Debug, Default)]
+pub struct Needs {
+    pub namespaces: BTreeSet<&'static str>,
+    pub capabilities: BTreeSet<&'static str>,
+}
+
+impl Needs {
+    // `service_user` is who the unit runs as; starting a program as them switches nothing.
+    pub fn of(action: &Action, service_user: Option<&str>) -> Needs {
+        let mut needs = Needs::default();
+        let limits = action.limits.clone().unwrap_or_default();
+        for namespace in &limits.namespaces {
+            needs.namespaces.insert(match namespace {
+                Namespace::Network => "net",
+                Namespace::Ipc => "ipc",
+                Namespace::Uts => "uts",
+                Namespace::Mount => "mnt",
+            });
+            // For unshare and for making the mounts private.
+            needs.capabilities.insert("CAP_SYS_ADMIN");
+        }
+        // systemd lets a process that is not root create a scope without asking polkit only with this.
+        if limits.needs_cgroup() {
+            needs.capabilities.insert("CAP_SYS_ADMIN");
+        }
+        if limits.nice.is_some_and(|nice| nice < 0) {
+            needs.capabilities.insert("CAP_SYS_NICE");
+        }
+        if action.user.as_deref().is_some_and(|user| Some(user) != service_user) {
+            needs.capabilities.extend(["CAP_SETUID", "CAP_SETGID"]);
+        }
+        needs
+    }
+
+    // What every action of the rules, fallbacks included, needs together.
+    pub fn of_rules(rules: &[Rule], default_action: &Action, service_user: Option<&str>) -> Needs {
+        let mut needs = Needs::default();
+        for rule in rules {
+            for action in std::iter::once(rule.action(default_action)).chain(rule.fallback_action()) {
+                let action_needs = Needs::of(&action, service_user);
+                needs.namespaces.extend(action_needs.namespaces);
+                needs.capabilities.extend(action_needs.capabilities);
+            }
+        }
+        needs
+    }
+}
+
+// The settings of a unit that limit what the programs it starts can be given.
+#[derive(Debug)]
+pub struct Sandbox {
+    // None for root.
+    user: Option<String>,
+    capabilities: BTreeSet<String>,
+    // RestrictNamespaces as written, None if it is not set.
+    namespaces: Option<String>,
+}
+
+impl Sandbox {
+    fn parse(unit: &str) -> Sandbox {
+        let setting = |name: &str| unit.lines().rev()
+            .find_map(|line| line.strip_prefix(name).and_then(|line| line.strip_prefix('=')))
+            .map(str::trim);
+        Sandbox {
+            user: setting("User").filter(|user| !user.is_empty() && *user != "root").map(str::to_string),
+            capabilities: setting("AmbientCapabilities").unwrap_or("").split_whitespace().map(str::to_string).collect(),
+            namespaces: setting("RestrictNamespaces").map(str::to_string),
+        }
+    }
+
+    fn allows_namespace(&self, namespace: &str) -> bool {
+        match self.namespaces.as_deref() {
+            None | Some("" | "no" | "false") => true,
+            Some("yes" | "true") => false,
+            Some(denied) if denied.starts_with('~') => !denied[1..].split_whitespace().any(|denied| denied == namespace),
+            Some(allowed) => allowed.split_whitespace().any(|allowed| allowed == namespace),
+        }
+    }
+
+    // What `action` needs that the unit does not allow, for messages.
+    pub fn missing(&self, action: &Action) -> Vec<String> {
+        let needs = Needs::of(action, self.user.as_deref());
+        let mut missing: Vec<String> = needs.namespaces.iter()
+            .filter(|namespace| !self.allows_namespace(namespace))
+            .map(|namespace| format!("does not allow {} namespaces", namespace))
+            .collect();
+        if let Some(user) = &self.user {
+            let capabilities: Vec<&str> = needs.capabilities.iter().copied()
+                .filter(|capability| !self.capabilities.contains(*capability))
+                .collect();
+            if !capabilities.is_empty() {
+                missing.push(format!("runs as {} without {}", user, capabilities.join(", ")));
+            }
+        }
+        missing
+    }
+}
+
+fn systemctl(args: &[&str]) -> Result<(), String> {
+    let status = Command::new("systemctl").args(args).status()
+        .map_err(|e| format!("Failed to run systemctl: {}", e))?;
+    if !status.success() {
+        return Err(format!("systemctl {} failed with {}", args.join(" "), status));
+    }
+    Ok(())
+}
+
+fn unit(executable: &Path, arguments: &[String], user: Option<&str>, needs: &Needs) -> String {
+    let exec_start: Vec<String> = std::iter::once(executable.to_string_lossy().into_owned())
+        .chain(arguments.iter().cloned())
+        .map(|argument| quote(&argument))
+        .collect();
+    let account = match user {
+        // Capturing needs a raw socket; anything else only if the rules need it.
+        Some(user) => {
+            let capabilities: Vec<&str> = std::iter::once("CAP_NET_RAW").chain(needs.capabilities.iter().copied()).collect();
+            format!("User={}\nAmbientCapabilities={}\nCapabilityBoundingSet={}\nNoNewPrivileges=yes\n",
+                    user, capabilities.join(" "), capabilities.join(" "))
+        },
+        None => String::new(),
+    };
+    let namespaces = if needs.namespaces.is_empty() {
+        String::from("yes")
+    } else {
+        needs.namespaces.iter().copied().collect::<Vec<_>>().join(" ")
+    };
+    format!("\
+# Written by `wolstart install`, removed by `wolstart uninstall`.
+[Unit]
+Description=WakeOnLan service
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=simple
+ExecStart={}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+StateDirectory=wolstart
+LogsDirectory=wolstart
+{}\
+# Programs started by the service inherit this sandbox, so /home and /tmp stay as they are.
+ProtectSystem=full
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6 AF_PACKET AF_NETLINK
+RestrictNamespaces={}
+RestrictRealtime=yes
+LockPersonality=yes
+SystemCallArchitectures=native
+
+[Install]
+WantedBy=multi-user.target
+", exec_start.join(" "), account, namespaces)
+}
+
+// Quotes one ExecStart argument, escaping systemd's specifiers and variable expansion too.
+fn quote(argument: &str) -> String {
+    let escaped = argument.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%").replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+// Splits an ExecStart line written by `quote`.
+fn split_exec_line(line: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_argument = true;
+            },
+            '\\' => {
+                current.extend(chars.next());
+                in_argument = true;
+            },
+            '%' | '$' if chars.peek() == Some(&c) => {
+                chars.next();
+                current.push(c);
+                in_argument = true;
+            },
+            ' ' | '\t' if !quoted => {
+                if in_argument {
+                    arguments.push(std::mem::take(&mut current));
+                    in_argument = false;
+                }
+            },
+            c => {
+                current.push(c);
+                in_argument = true;
+            },
+        }
+    }
+    if in_argument {
+        arguments.push(current);
+    }
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_lines_round_trip() {
+        let arguments: Vec<String> = [
+            "/opt/Wol Start/wolstart", "kodi", "/usr/bin/kodi", "192.168.1", "info",
+            "/etc/wolstart/my \"home\" config.toml", "100%", "%h/wolstart.toml", "$HOME", "${HOME}",
+            "back\\slash", "tab\there", "", "'single'",
+        ].iter().map(|argument| argument.to_string()).collect();
+        let line: Vec<String> = arguments.iter().map(|argument| quote(argument)).collect();
+        let line = line.join(" ");
+        assert_eq!(split_exec_line(&line), arguments);
+        // systemd would expand a single % or $.
+        assert!(!line.replace("%%", "").contains('%'));
+        assert!(!line.replace("$$", "").contains('$'));
+    }
+
+    #[test]
+    fn reads_the_arguments_of_the_written_unit() {
+        let arguments = vec![String::from("kodi"), String::from("/usr/bin/kodi"), String::from("192.168.1"),
+                             String::from("info"), String::from("/etc/wol start/50% off.toml")];
+        let written = unit(Path::new("/usr/local/bin/wolstart"), &arguments, None, &Needs::default());
+        let line = written.lines().find_map(|line| line.strip_prefix("ExecStart=")).unwrap();
+        assert_eq!(split_exec_line(line)[1..], arguments);
+    }
+
+    #[test]
+    fn sizes_the_unit_to_the_rules() {
+        let config: Config = toml::from_str(r#"
+            [[rules]]
+            name = "nice"
+            user = "alice"
+            limits = { nice = -5, namespaces = ["network"] }
+        "#).unwrap();
+        let default_action = Action::new("kodi", "/usr/bin/kodi");
+        let needs = Needs::of_rules(&config.rules, &default_action, Some("wol"));
+        let written = unit(Path::new("/usr/local/bin/wolstart"), &[], Some("wol"), &needs);
+        assert!(written.contains("\nAmbientCapabilities=CAP_NET_RAW CAP_SETGID CAP_SETUID CAP_SYS_ADMIN CAP_SYS_NICE\n"));
+        assert!(written.contains("\nRestrictNamespaces=net\n"));
+        assert!(Sandbox::parse(&written).missing(&config.rules[0].action(&default_action)).is_empty());
+
+        // Starting programs as the service's own user needs no capabilities beyond capturing.
+        let needs = Needs::of_rules(&config.rules, &default_action, Some("alice"));
+        assert_eq!(needs.capabilities.into_iter().collect::<Vec<_>>(), ["CAP_SYS_ADMIN", "CAP_SYS_NICE"]);
+
+        let plain = unit(Path::new("/usr/local/bin/wolstart"), &[], Some("wol"), &Needs::default());
+        assert!(plain.contains("\nCapabilityBoundingSet=CAP_NET_RAW\n"));
+        assert!(plain.contains("\nRestrictNamespaces=yes\n"));
+        assert_eq!(Sandbox::parse(&plain).missing(&config.rules[0].action(&default_action)), [
+            "does not allow net namespaces",
+            "runs as wol without CAP_SETGID, CAP_SETUID, CAP_SYS_ADMIN, CAP_SYS_NICE",
+        ]);
+        // As root only the namespaces can be missing.
+        let root = unit(Path::new("/usr/local/bin/wolstart"), &[], None, &Needs::default());
+        assert_eq!(Sandbox::parse(&root).missing(&config.rules[0].action(&default_action)), ["does not allow net namespaces"]);
+    }
+}